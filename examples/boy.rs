@@ -1,26 +1,29 @@
-use bevy::{
-    prelude::*, render::mesh::skinning::SkinnedMeshInverseBindposes, utils::HashMap,
-    window::PresentMode,
-};
+use bevy::{asset::ChangeWatcher, prelude::*, window::PresentMode};
 use bevy_egui::EguiPlugin;
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use bevy_mod_picking::{prelude::PickingInteraction, DefaultPickingPlugins};
 use bevy_panorbit_camera::{PanOrbitCamera, PanOrbitCameraPlugin};
 use bevy_transform_gizmo::{GizmoTransformable, TransformGizmo, TransformGizmoPlugin};
-use bevy_vox_mesh::{vox_scene_info::VoxSceneInfo, VoxMeshPlugin};
+use bevy_vox_mesh::VoxMeshPlugin;
 use bevy_vox_mesh_animation::{
-    dealers::{Body0Dealers, Body1Dealers, CommonDealers},
-    perpare_player_data,
-    pose::{BoyEntity, PoseEditPlugin},
+    character::VoxCharacterPlugin,
+    manifest::{VoxCharacterAsset, VoxCharacterManifestBundle},
+    palette::PaletteOverride,
+    pose::{PoseEditPlugin, PoseEditTarget},
     types::{AnimatedJoint, LeftArm, LeftLeg, RightArm, RightHand, RightLeg},
-    DealWithJoints,
+    variants::{SetVariant, VariantState},
+    vox_animation::{VoxAnimationPlayer, VoxAnimationPlugin},
 };
-use std::f32::consts::PI;
+use std::{f32::consts::PI, time::Duration};
 
 fn main() {
     App::default()
         .add_plugins((
-            DefaultPlugins,
+            // 保存 boy.vox 之后角色会在原地重新生成, 保留当前的姿态
+            DefaultPlugins.set(AssetPlugin {
+                watch_for_changes: ChangeWatcher::with_delay(Duration::from_millis(200)),
+                ..default()
+            }),
             DefaultPickingPlugins,
             TransformGizmoPlugin::new(
                 Quat::from_rotation_y(-0.2), // Align the gizmo to a different coordinate system.
@@ -33,18 +36,12 @@ fn main() {
         .add_plugins(WorldInspectorPlugin::new())
         .add_plugins(VoxMeshPlugin::default())
         .add_plugins(PoseEditPlugin)
-        .insert_resource(BoyMate {
-            handle: None,
-            mate: None,
-        })
-        .insert_resource(BoyEntity { boy_entity: None })
-        .insert_resource(FaceNow::default())
+        .add_plugins(VoxAnimationPlugin)
+        .add_plugins(VoxCharacterPlugin)
         .add_systems(Startup, setup)
         .add_systems(
             Update,
             (
-                load_mate,
-                load_boy,
                 toggle_faces,
                 show_pick,
                 toggle_visible_animated_joint,
@@ -56,26 +53,6 @@ fn main() {
         .run();
 }
 
-#[derive(Debug, Resource, Clone)]
-pub struct BoyMate {
-    pub handle: Option<Handle<VoxSceneInfo>>,
-    pub mate: Option<VoxSceneInfo>,
-}
-
-#[derive(Debug, Resource, Clone)]
-pub struct FaceNow {
-    pub now_face: &'static str,
-}
-
-impl Default for FaceNow {
-    fn default() -> Self {
-        Self { now_face: "face0" }
-    }
-}
-
-#[derive(Debug, Component)]
-pub struct ReadyEntity;
-
 // 自动切换镜头的转动
 fn auto_toggle_camera_controls_system(
     mut pan_orbit_query: Query<&mut PanOrbitCamera>,
@@ -110,30 +87,21 @@ fn toggle_camera_controls_system(
     }
 }
 
+// Tab 切换所有角色的表情
 fn toggle_faces(
     keyboard_input: Res<Input<KeyCode>>,
-    mut query: Query<(Entity, &Name, &mut Visibility)>,
-    mut face_now: ResMut<FaceNow>,
+    characters: Query<(Entity, &VariantState)>,
+    mut set_variant: EventWriter<SetVariant>,
 ) {
-    let faces = vec!["face0", "face1", "face2", "face3"];
     if keyboard_input.just_pressed(KeyCode::Tab) {
-        if let Some(index) = faces.iter().position(|&x| x == face_now.now_face) {
-            let next_index = if index == faces.len() - 1 {
-                0
-            } else {
-                index + 1
-            };
-            let next_face = faces[next_index];
-            for (_, name, mut visibility) in query.iter_mut() {
-                if faces.contains(&name.as_str()) {
-                    if name.as_str() == next_face {
-                        *visibility.as_mut() = Visibility::Inherited;
-                    } else {
-                        *visibility.as_mut() = Visibility::Hidden;
-                    }
-                }
+        for (character, state) in characters.iter() {
+            if let Some(next_face) = state.next_value("face") {
+                set_variant.send(SetVariant {
+                    character,
+                    group: String::from("face"),
+                    value: next_face.to_string(),
+                });
             }
-            face_now.now_face = next_face;
         }
     }
 }
@@ -143,10 +111,10 @@ fn show_joints(
     time: Res<Time>,
     mut gizmos: Gizmos,
     mut query: Query<(&mut Transform, &RightArm)>,
-    boy_entity: Res<BoyEntity>,
+    target: Res<PoseEditTarget>,
     transform_query: Query<&Transform, Without<RightArm>>,
 ) {
-    if let Some(entity) = boy_entity.boy_entity {
+    if let Some(entity) = target.character {
         if let Ok(trf) = transform_query.get(entity) {
             // 这里可以进行其他的处理?
             {
@@ -190,104 +158,34 @@ fn toggle_visible_animated_joint(
     }
 }
 
-fn load_boy(
-    mut commands: Commands,
-    boy_mate: Res<BoyMate>,
-    mut boy_entity: ResMut<BoyEntity>,
-    assets: Res<AssetServer>,
-    mut stdmats: ResMut<Assets<StandardMaterial>>,
-    mut mesh_assets: ResMut<Assets<Mesh>>,
-    mut skinned_mesh_inverse_bindposes_assets: ResMut<Assets<SkinnedMeshInverseBindposes>>,
-) {
-    if let Some(_entity) = boy_entity.boy_entity {
-        // 这里可以进行其他的处理?
-    } else {
-        if let Some(mate_data) = boy_mate.mate.clone() {
-            if mate_data.all_loaded("boy.vox", mesh_assets.as_ref(), assets.as_ref()) {
-                let mut config_map: HashMap<String, Box<dyn DealWithJoints>> = HashMap::new();
-                let dealer = CommonDealers {};
-                let body1_dealer = Body1Dealers {};
-                let body0_dealer = Body0Dealers {};
-                config_map.insert(String::from("face0"), Box::new(dealer.clone()));
-                config_map.insert(String::from("face1"), Box::new(dealer.clone()));
-                config_map.insert(String::from("face2"), Box::new(dealer.clone()));
-                config_map.insert(String::from("face3"), Box::new(dealer.clone()));
-                config_map.insert(String::from("body0"), Box::new(body0_dealer.clone()));
-                config_map.insert(String::from("body1"), Box::new(body1_dealer.clone()));
-
-                let entitiys = perpare_player_data(
-                    "boy.vox",
-                    mate_data.clone(),
-                    &mut commands,
-                    assets.as_ref(),
-                    stdmats.add(Color::rgb(1., 1., 1.).into()),
-                    mesh_assets.as_mut(),
-                    skinned_mesh_inverse_bindposes_assets.as_mut(),
-                    config_map,
-                    stdmats.as_mut(),
-                );
-                // TODO 这里使用其他方法准备数据!
-                let boy = commands
-                    .spawn((
-                        ReadyEntity,
-                        Visibility::Inherited,
-                        ComputedVisibility::HIDDEN,
-                        GlobalTransform::IDENTITY,
-                        Transform {
-                            translation: Vec3 {
-                                x: 0.0,
-                                y: 1.0 / 40. * 40., // height is 80 so the button is scale*80/2
-                                z: 0.0,
-                            },
-                            scale: Vec3 {
-                                x: 1.0 / 40.,
-                                y: 1.0 / 40.,
-                                z: 1.0 / 40.,
-                            },
-                            ..Default::default()
-                        } * Transform::from_rotation(Quat::from_axis_angle(Vec3::Y, PI)),
-                    ))
-                    .id();
-                for s in vec![
-                    &String::from("face0"),
-                    &String::from("face1"),
-                    &String::from("face2"),
-                    &String::from("face3"),
-                    &String::from("body0"),
-                    &String::from("body1"),
-                ] {
-                    if let Some(entity) = entitiys.get(s) {
-                        commands.entity(boy).add_child(*entity);
-                    }
-                }
-                boy_entity.boy_entity = Some(boy);
-            }
-        }
-    }
-}
-
-fn load_mate(mate_assets: Res<Assets<VoxSceneInfo>>, mut boy_mate: ResMut<BoyMate>) {
-    if let Some(handle) = boy_mate.handle.clone() {
-        match boy_mate.mate {
-            Some(_) => {}
-            None => {
-                if let Some(mate) = mate_assets.get(&handle) {
-                    boy_mate.mate = Some(mate.clone());
-                }
-            }
-        }
-    }
-}
-
 fn setup(
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut stdmats: ResMut<Assets<StandardMaterial>>,
-    mut boy_mate: ResMut<BoyMate>,
     assets: Res<AssetServer>,
 ) {
-    let mate_data_handle: Handle<VoxSceneInfo> = assets.load("boy.vox#scene");
-    boy_mate.handle = Some(mate_data_handle);
+    // 节点配置, 缩放和表情都写在 boy.voxrig 中
+    // 同一个角色可以生成多个, 姿态编辑器里选择要编辑的那个
+    // 第二个角色的四肢使用按距离混合的权重, 见 boy_smooth.voxrig
+    let manifests: [Handle<VoxCharacterAsset>; 2] =
+        [assets.load("boy.voxrig"), assets.load("boy_smooth.voxrig")];
+    for (index, (x, manifest)) in [-0.8, 0.8].into_iter().zip(manifests).enumerate() {
+        let mut boy = commands.spawn((
+            VoxCharacterManifestBundle {
+                spatial: SpatialBundle::from_transform(
+                    Transform::from_xyz(x, 0.0, 0.0) // vox 节点的 `_t` 已经把模型底部放在原点
+                        * Transform::from_rotation(Quat::from_axis_angle(Vec3::Y, PI)),
+                ),
+                ..VoxCharacterManifestBundle::new(manifest)
+            },
+            Name::new(format!("boy{}", index)),
+            VoxAnimationPlayer::default(),
+        ));
+        // 第二个角色替换调色板中的一个颜色, 两个角色共用同一个 vox 文件
+        if index == 1 {
+            boy.insert(PaletteOverride::new().with_color(9, Color::rgb(0.35, 0.55, 0.95)));
+        }
+    }
 
     // 添加环境光照
     commands.insert_resource(AmbientLight {
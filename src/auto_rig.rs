@@ -0,0 +1,243 @@
+// 从 vox 场景中读取骨骼
+// 在 MagicaVoxel 里放置名字为 `joint:xxx` 的空节点作为关节
+// 关节节点下可以放一个小模型方便摆放, 生成角色时整个跳过, 见 [`is_joint_node`]
+
+use bevy::{
+    log::warn,
+    prelude::{Mat4, Vec3},
+};
+use dot_vox::SceneNode;
+
+use crate::{
+    rig::{JointDescription, JointSphere, RigDescription, WeightMode},
+    vox_transform::node_transform,
+};
+
+/// 关节节点名字的前缀, `joint:left_arm` 对应名字为 `left_arm` 的关节
+pub const JOINT_PREFIX: &str = "joint:";
+/// 关节节点上可选的父关节属性, 不填时使用外层最近的关节节点
+pub const PARENT_ATTRIBUTE: &str = "parent";
+
+/// 名字为 `joint:xxx` 的节点, 只用来标记关节位置, 它和它的子节点都不生成
+pub fn is_joint_node(name: &str) -> bool {
+    name.starts_with(JOINT_PREFIX)
+}
+
+struct FoundJoint {
+    name: String,
+    parent: Option<String>,
+    position: Vec3,
+}
+
+struct Walk {
+    target: u32,
+    joints: Vec<FoundJoint>,
+    // 目标节点中第一个模型的变换, 也就是 mesh 的坐标系, 关节节点下的标记模型不算
+    origin: Option<Mat4>,
+}
+
+/// 从场景中第一个名字为 `target` 的节点下读取关节, 见 [`rig_from_vox_node`]
+/// 路径和通配符先用 [`crate::node_match::match_scene_nodes`] 找到节点
+pub fn rig_from_vox_scene(
+    scenes: &[SceneNode],
+    target: &str,
+    weight_mode: WeightMode,
+) -> Option<RigDescription> {
+    let index = scenes.iter().position(|node| match node {
+        SceneNode::Transform { attributes, .. } => {
+            attributes.get("_name").map(|name| name.as_str()) == Some(target)
+        }
+        _ => false,
+    })?;
+    rig_from_vox_node(scenes, index as u32, weight_mode)
+}
+
+/// 从序号为 `target` 的 transform 节点下读取关节, 生成 [`RigDescription`]
+/// 关节位置转换到 `target` 下第一个模型的坐标系中, 找不到 `target` 或者没有关节时返回 `None`
+pub fn rig_from_vox_node(
+    scenes: &[SceneNode],
+    target: u32,
+    weight_mode: WeightMode,
+) -> Option<RigDescription> {
+    let mut walk = Walk {
+        target,
+        joints: Vec::new(),
+        origin: None,
+    };
+    walk_node(scenes, 0, Mat4::IDENTITY, None, false, false, &mut walk);
+    if walk.joints.is_empty() {
+        return None;
+    }
+    let to_mesh = walk.origin.unwrap_or(Mat4::IDENTITY).inverse();
+
+    let mut joints: Vec<JointDescription> = walk
+        .joints
+        .into_iter()
+        .map(|joint| JointDescription {
+            name: joint.name,
+            parent: joint.parent,
+            pivot: to_mesh.transform_point3(joint.position).to_array(),
+            rotation: None,
+            marker: None,
+            sphere: Some(JointSphere {
+                radius: 3.0,
+                color: [1.0, 1.0, 1.0, 0.5],
+            }),
+        })
+        .collect();
+    sort_parents_first(&mut joints);
+
+    Some(RigDescription {
+        joints,
+        weight_mode,
+        ..Default::default()
+    })
+}
+
+fn walk_node(
+    scenes: &[SceneNode],
+    index: u32,
+    parent_matrix: Mat4,
+    parent_joint: Option<&str>,
+    in_target: bool,
+    in_joint: bool,
+    walk: &mut Walk,
+) {
+    let Some(node) = scenes.get(index as usize) else {
+        warn!("scene node {} does not exist", index);
+        return;
+    };
+    match node {
+        SceneNode::Transform {
+            attributes,
+            frames,
+            child,
+            layer_id: _,
+        } => {
+            let matrix = parent_matrix * node_transform(frames).compute_matrix();
+            let name = attributes.get("_name").map(|name| name.as_str());
+            let in_target = in_target || index == walk.target;
+            let joint_name = name.and_then(|name| name.strip_prefix(JOINT_PREFIX));
+            if in_target {
+                if let Some(joint_name) = joint_name {
+                    walk.joints.push(FoundJoint {
+                        name: joint_name.to_string(),
+                        parent: attributes
+                            .get(PARENT_ATTRIBUTE)
+                            .cloned()
+                            .or_else(|| parent_joint.map(|name| name.to_string())),
+                        position: matrix.w_axis.truncate(),
+                    });
+                }
+            }
+            let parent_joint = joint_name.or(parent_joint);
+            let in_joint = in_joint || joint_name.is_some();
+            if in_target && !in_joint && walk.origin.is_none() {
+                if let Some(SceneNode::Shape { .. }) = scenes.get(*child as usize) {
+                    walk.origin = Some(matrix);
+                }
+            }
+            walk_node(
+                scenes,
+                *child,
+                matrix,
+                parent_joint,
+                in_target,
+                in_joint,
+                walk,
+            );
+        }
+        SceneNode::Group {
+            attributes: _,
+            children,
+        } => {
+            for child in children {
+                walk_node(
+                    scenes,
+                    *child,
+                    parent_matrix,
+                    parent_joint,
+                    in_target,
+                    in_joint,
+                    walk,
+                );
+            }
+        }
+        SceneNode::Shape { .. } => {}
+    }
+}
+
+// RigDescription 要求父关节写在子关节前面
+fn sort_parents_first(joints: &mut Vec<JointDescription>) {
+    let mut sorted: Vec<JointDescription> = Vec::with_capacity(joints.len());
+    let mut rest = std::mem::take(joints);
+    while !rest.is_empty() {
+        let before = rest.len();
+        let (ready, pending): (Vec<_>, Vec<_>) = rest.into_iter().partition(|joint| {
+            joint
+                .parent
+                .as_ref()
+                .is_none_or(|parent| sorted.iter().any(|done| &done.name == parent))
+        });
+        sorted.extend(ready);
+        rest = pending;
+        if rest.len() == before {
+            // 父关节不存在或者有环, 剩下的都当作根关节
+            for joint in rest.iter_mut() {
+                warn!(
+                    "joint {}: parent {:?} not found, treated as root",
+                    joint.name, joint.parent
+                );
+                joint.parent = None;
+            }
+        }
+    }
+    *joints = sorted;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn joint(name: &str, parent: Option<&str>) -> JointDescription {
+        JointDescription {
+            name: name.to_string(),
+            parent: parent.map(str::to_string),
+            pivot: [0.0; 3],
+            rotation: None,
+            marker: None,
+            sphere: None,
+        }
+    }
+
+    fn names(joints: &[JointDescription]) -> Vec<&str> {
+        joints.iter().map(|joint| joint.name.as_str()).collect()
+    }
+
+    #[test]
+    fn parents_before_children() {
+        let mut joints = vec![
+            joint("hand", Some("arm")),
+            joint("arm", Some("body")),
+            joint("body", None),
+            joint("leg", Some("body")),
+        ];
+        sort_parents_first(&mut joints);
+        assert_eq!(names(&joints), ["body", "arm", "leg", "hand"]);
+    }
+
+    #[test]
+    fn missing_parent_becomes_root() {
+        let mut joints = vec![joint("arm", Some("body")), joint("leg", None)];
+        sort_parents_first(&mut joints);
+        assert_eq!(names(&joints), ["leg", "arm"]);
+        assert_eq!(joints[1].parent, None);
+    }
+
+    #[test]
+    fn cycle_becomes_roots() {
+        let mut joints = vec![joint("a", Some("b")), joint("b", Some("a"))];
+        sort_parents_first(&mut joints);
+        assert!(joints.iter().all(|joint| joint.parent.is_none()));
+    }
+}
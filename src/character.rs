@@ -0,0 +1,256 @@
+// 角色插件
+// 生成 VoxCharacterBundle 之后, 等场景和所有模型加载完成, 再按配置生成层级
+
+use bevy::{
+    ecs::system::SystemParam,
+    log::error,
+    prelude::{
+        App, AssetServer, Assets, BuildChildren, Bundle, Color, Commands, Component, Entity, Event,
+        EventWriter, FromWorld, Handle, Mesh, Plugin, Query, Res, ResMut, Resource, SpatialBundle,
+        StandardMaterial, Update, Without, World,
+    },
+    render::mesh::skinning::SkinnedMeshInverseBindposes,
+    utils::HashMap,
+};
+use bevy_vox_mesh::vox_scene_info::VoxSceneInfo;
+
+use crate::{
+    error::VoxAnimError,
+    joint_map::{JointMap, JointMapPlugin},
+    layers::{VoxLayers, VoxLayersPlugin},
+    manifest::VoxCharacterManifestPlugin,
+    node_match::FallbackPolicy,
+    palette::VoxPalettePlugin,
+    perpare_player_data,
+    reload::VoxReloadPlugin,
+    sockets::VoxSocketsPlugin,
+    validation::SkinValidationPlugin,
+    variants::{VariantState, VoxVariantsPlugin},
+    DealWithJoints,
+};
+
+/// 场景中节点名字 (也可以是路径或者通配符, 见 [`crate::node_match`]) 到 dealer 的配置
+#[derive(Default, Component)]
+pub struct VoxCharacterRig {
+    pub parts: HashMap<String, Box<dyn DealWithJoints>>,
+    /// 没有配置的模型怎么处理, 默认不生成
+    pub fallback: FallbackPolicy,
+    /// 互斥的部件, 组名到部件名字, 每组默认显示第一个
+    pub variants: HashMap<String, Vec<String>>,
+}
+
+impl VoxCharacterRig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 名字为 `name` 的节点使用 `dealer` 生成
+    pub fn with_part(mut self, name: impl Into<String>, dealer: impl DealWithJoints) -> Self {
+        self.parts.insert(name.into(), Box::new(dealer));
+        self
+    }
+
+    pub fn with_fallback(mut self, fallback: FallbackPolicy) -> Self {
+        self.fallback = fallback;
+        self
+    }
+
+    /// `values` 是部件名字, 和 `with_part` 的 key 一样
+    pub fn with_variant_group(
+        mut self,
+        group: impl Into<String>,
+        values: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.variants
+            .insert(group.into(), values.into_iter().map(Into::into).collect());
+        self
+    }
+}
+
+/// 生成一个角色, 加载完成之后子节点才会出现
+#[derive(Bundle)]
+pub struct VoxCharacterBundle {
+    /// `xxx.vox#scene`
+    pub scene: Handle<VoxSceneInfo>,
+    pub rig: VoxCharacterRig,
+    pub joint_map: JointMap,
+    pub spatial: SpatialBundle,
+}
+
+impl VoxCharacterBundle {
+    pub fn new(scene: Handle<VoxSceneInfo>, rig: VoxCharacterRig) -> Self {
+        Self {
+            scene,
+            rig,
+            joint_map: JointMap::default(),
+            spatial: SpatialBundle::default(),
+        }
+    }
+}
+
+/// 已经生成完成的角色, 记录每个配置的 key 匹配到的节点生成的实体
+#[derive(Debug, Clone, Component)]
+pub struct VoxCharacter {
+    pub parts: HashMap<String, Vec<Entity>>,
+}
+
+/// 角色生成完成
+#[derive(Debug, Clone, Copy, Event)]
+pub struct CharacterReady(pub Entity);
+
+/// 生成失败的角色, vox 文件修改之前不会再重试
+#[derive(Debug, Clone, Component)]
+pub struct VoxCharacterError(pub VoxAnimError);
+
+/// 所有角色共用的材质, 颜色来自顶点颜色
+#[derive(Debug, Clone, Resource)]
+pub struct VoxCharacterMaterial(pub Handle<StandardMaterial>);
+
+impl FromWorld for VoxCharacterMaterial {
+    fn from_world(world: &mut World) -> Self {
+        let mut materials = world.resource_mut::<Assets<StandardMaterial>>();
+        Self(materials.add(Color::rgb(1., 1., 1.).into()))
+    }
+}
+
+pub struct VoxCharacterPlugin;
+
+impl Plugin for VoxCharacterPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<JointMapPlugin>() {
+            app.add_plugins(JointMapPlugin);
+        }
+        if !app.is_plugin_added::<VoxCharacterManifestPlugin>() {
+            app.add_plugins(VoxCharacterManifestPlugin);
+        }
+        if !app.is_plugin_added::<VoxLayersPlugin>() {
+            app.add_plugins(VoxLayersPlugin);
+        }
+        if !app.is_plugin_added::<VoxVariantsPlugin>() {
+            app.add_plugins(VoxVariantsPlugin);
+        }
+        if !app.is_plugin_added::<VoxPalettePlugin>() {
+            app.add_plugins(VoxPalettePlugin);
+        }
+        if !app.is_plugin_added::<VoxReloadPlugin>() {
+            app.add_plugins(VoxReloadPlugin);
+        }
+        if !app.is_plugin_added::<VoxSocketsPlugin>() {
+            app.add_plugins(VoxSocketsPlugin);
+        }
+        if !app.is_plugin_added::<SkinValidationPlugin>() {
+            app.add_plugins(SkinValidationPlugin);
+        }
+        app.add_event::<CharacterReady>()
+            .init_resource::<VoxCharacterMaterial>()
+            .add_systems(Update, spawn_characters);
+    }
+}
+
+/// `boy.vox#scene` 对应的模型路径 `boy.vox`
+pub fn scene_base_path(asset_server: &AssetServer, scene: &Handle<VoxSceneInfo>) -> Option<String> {
+    asset_server
+        .get_handle_path(scene)
+        .map(|path| path.path().to_string_lossy().into_owned())
+}
+
+/// 生成角色需要的资源, 首次生成和热重载共用
+#[derive(SystemParam)]
+pub(crate) struct CharacterSpawner<'w> {
+    scenes: Res<'w, Assets<VoxSceneInfo>>,
+    asset_server: Res<'w, AssetServer>,
+    material: Res<'w, VoxCharacterMaterial>,
+    mesh_assets: ResMut<'w, Assets<Mesh>>,
+    skinned_mesh_inverse_bindposes_assets: ResMut<'w, Assets<SkinnedMeshInverseBindposes>>,
+    materials: ResMut<'w, Assets<StandardMaterial>>,
+}
+
+impl CharacterSpawner<'_> {
+    /// 场景和所有模型加载完成之前返回 `None`
+    pub(crate) fn spawn(
+        &mut self,
+        commands: &mut Commands,
+        scene: &Handle<VoxSceneInfo>,
+        rig: &VoxCharacterRig,
+    ) -> Option<Result<HashMap<String, Vec<Entity>>, VoxAnimError>> {
+        let info = self.scenes.get(scene)?;
+        let base_path = scene_base_path(&self.asset_server, scene)?;
+        if !info.all_loaded(&base_path, &self.mesh_assets, &self.asset_server) {
+            return None;
+        }
+        Some(perpare_player_data(
+            &base_path,
+            info.clone(),
+            commands,
+            &self.asset_server,
+            self.material.0.clone(),
+            &mut self.mesh_assets,
+            &mut self.skinned_mesh_inverse_bindposes_assets,
+            &rig.parts,
+            rig.fallback,
+            &mut self.materials,
+        ))
+    }
+
+    pub(crate) fn layer_map(&self, scene: &Handle<VoxSceneInfo>) -> Option<&HashMap<u32, bool>> {
+        self.scenes.get(scene).map(|info| &info.layer_map)
+    }
+}
+
+/// 按名字排序, 保证每次生成的顺序一样
+pub(crate) fn sorted_children(parts: &HashMap<String, Vec<Entity>>) -> Vec<Entity> {
+    let mut names: Vec<&String> = parts.keys().collect();
+    names.sort();
+    names
+        .iter()
+        .flat_map(|name| parts[*name].iter().copied())
+        .collect()
+}
+
+type PendingCharacters<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Handle<VoxSceneInfo>,
+        &'static VoxCharacterRig,
+        Option<&'static VoxLayers>,
+    ),
+    (Without<VoxCharacter>, Without<VoxCharacterError>),
+>;
+
+fn spawn_characters(
+    mut commands: Commands,
+    pending: PendingCharacters,
+    mut spawner: CharacterSpawner,
+    mut ready: EventWriter<CharacterReady>,
+) {
+    for (character, scene, rig, layers) in pending.iter() {
+        let Some(result) = spawner.spawn(&mut commands, scene, rig) else {
+            continue;
+        };
+        let parts = match result {
+            Ok(parts) => parts,
+            Err(err) => {
+                error!("failed to spawn character {:?}: {}", character, err);
+                commands.entity(character).insert(VoxCharacterError(err));
+                continue;
+            }
+        };
+        let children = sorted_children(&parts);
+        let mut character_commands = commands.entity(character);
+        character_commands
+            .push_children(&children)
+            .insert(VoxCharacter { parts });
+        // 没有从 vox 文件读取图层时只有 id 和显示状态
+        if layers.is_none() {
+            if let Some(layer_map) = spawner.layer_map(scene) {
+                character_commands.insert(VoxLayers::from_layer_map(layer_map));
+            }
+        }
+        if !rig.variants.is_empty() {
+            character_commands.insert(VariantState::new(rig.variants.clone()));
+        }
+        ready.send(CharacterReady(character));
+    }
+}
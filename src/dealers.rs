@@ -1,23 +1,22 @@
-use std::slice::Iter;
-
 use bevy::{
+    ecs::system::EntityCommands,
+    log::warn,
     prelude::{
-        shape, AlphaMode, Assets, BuildChildren, Color, Handle, Mat4, Mesh, PbrBundle,
-        StandardMaterial, Transform, Vec3, Vec4,
-    },
-    render::{
-        mesh::{
-            skinning::{SkinnedMesh, SkinnedMeshInverseBindposes},
-            VertexAttributeValues,
-        },
-        render_resource::PrimitiveTopology,
+        shape, AlphaMode, Assets, BuildChildren, Color, Handle, Mesh, PbrBundle, StandardMaterial,
+        Transform, Vec3,
     },
-    transform::TransformBundle,
+    render::mesh::skinning::SkinnedMeshInverseBindposes,
 };
-use bevy_mod_mesh_tools::{mesh_len, mesh_normals, mesh_positions, mesh_uvs};
 
 use crate::{
-    types::{AnimatedJoint, Body, LeftArm, LeftHand, LeftLeg, RightArm, RightHand, RightLeg},
+    error::VoxAnimError,
+    mesh_helper::{skinned_mesh_from, vertex_colors, vertex_positions},
+    rig::{RigDescription, WeightMode},
+    skin_cache::{CachedSkin, SkinCache},
+    sockets::spawn_socket,
+    types::{AnimatedJoint, Joint, RigidPart},
+    validation::{validate_weights, SkinError},
+    weights::{duplicate_colors, palette_index, vox_palette_colors},
     DealWithJoints,
 };
 
@@ -34,209 +33,148 @@ impl DealWithJoints for CommonDealers {
         _skinned_mesh_inverse_bindposes_assets: &mut bevy::prelude::Assets<
             bevy::render::mesh::skinning::SkinnedMeshInverseBindposes,
         >,
-        materials: &mut Assets<StandardMaterial>,
-    ) -> Option<bevy::prelude::Entity> {
-        if let Some(mesh) = mesh_assets.get(&handle) {
-            let entity = commands
-                .spawn(PbrBundle {
-                    transform: Transform::IDENTITY,
-                    // 为了测试临时隐藏
-                    // visibility: bevy::prelude::Visibility::Hidden,
-                    mesh: mesh_assets.add(mesh.clone()),
-                    material: material_handle.clone(),
-                    ..Default::default()
-                })
-                .id();
-            return Some(entity);
+        _materials: &mut Assets<StandardMaterial>,
+    ) -> Result<bevy::prelude::Entity, VoxAnimError> {
+        // 不修改模型, 所有实例共用原来的 mesh
+        if !mesh_assets.contains(&handle) {
+            return Err(VoxAnimError::mesh_not_loaded(&handle));
         }
-        None
+        let entity = commands
+            .spawn(PbrBundle {
+                transform: Transform::IDENTITY,
+                // 为了测试临时隐藏
+                // visibility: bevy::prelude::Visibility::Hidden,
+                mesh: handle,
+                material: material_handle.clone(),
+                ..Default::default()
+            })
+            .id();
+        Ok(entity)
     }
 }
 
+/// 刚性绑定: 每个模型整个跟随包住它的 Transform 节点
+/// 关节的轴心就是 Transform 节点的位置, 不需要任何顶点权重
+/// 多个模型放在 MagicaVoxel 的组里就是一个可以摆姿势的层级
 #[derive(Debug, Clone)]
-pub struct Body1Dealers;
+pub struct RigidPartDealers;
 
-impl DealWithJoints for Body1Dealers {
+impl DealWithJoints for RigidPartDealers {
     fn deal(
         &self,
         handle: Handle<Mesh>,
         commands: &mut bevy::prelude::Commands,
         mesh_assets: &mut bevy::prelude::Assets<Mesh>,
         material_handle: Handle<bevy::prelude::StandardMaterial>,
-        skinned_mesh_inverse_bindposes_assets: &mut bevy::prelude::Assets<
-            bevy::render::mesh::skinning::SkinnedMeshInverseBindposes,
+        _skinned_mesh_inverse_bindposes_assets: &mut bevy::prelude::Assets<
+            SkinnedMeshInverseBindposes,
         >,
-        materials: &mut Assets<StandardMaterial>,
-    ) -> Option<bevy::prelude::Entity> {
-        // 获取mesh 然后添加
-        if let Some(mesh) = mesh_assets.get(&handle) {
-            let mut joints_mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        _materials: &mut Assets<StandardMaterial>,
+    ) -> Result<bevy::prelude::Entity, VoxAnimError> {
+        // 模型不需要复制, 直接使用原来的 mesh
+        if !mesh_assets.contains(&handle) {
+            return Err(VoxAnimError::mesh_not_loaded(&handle));
+        }
+        let entity = commands
+            .spawn(PbrBundle {
+                mesh: handle,
+                material: material_handle,
+                ..Default::default()
+            })
+            .id();
+        Ok(entity)
+    }
 
-            let positions_iter = mesh_positions(mesh);
-            // 这要重重新转成数据
-            let mesh_normals_iter = mesh_normals(mesh);
-            let mesh_uvs_iter = mesh_uvs(mesh);
-            let mesh_color_iter = mesh_color(mesh);
-            // TODO 生成 joint index 并且配置权重
-            joints_mesh.insert_attribute(
-                Mesh::ATTRIBUTE_JOINT_INDEX,
-                VertexAttributeValues::Uint16x4(vec![[0, 1, 2, 3]; mesh_len(mesh)]),
-            );
-            // 配置权限
-            // 这使用 x = 0 和 y = 0 可以分出四个象限 不同象限的 对应的不一样
-            //          |
-            //     L.H  |  R.H
-            //    ------0--------
-            //     L.L  |  R.L
-            //          |
-            // left hand and right hand
-            // left leg  and right leg
-            let joint_weight: Vec<[f32; 4]> = positions_iter
-                .clone()
-                .map(|v3| {
-                    let Vec3 { x, y, z: _ } = v3;
-                    match (*x > 0.0, *y > -20.0) {
-                        (true, true) => [1.0, 0.0, 0.0, 0.0],
-                        (true, false) => [0.0, 0.0, 0.0, 1.0],
-                        (false, true) => [0.0, 1.0, 0.0, 0.0],
-                        (false, false) => [0.0, 0.0, 0.1, 0.0],
-                    }
-                })
-                .collect();
-            joints_mesh.insert_attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT, joint_weight);
-            let position: Vec<[f32; 3]> = positions_iter.map(|x| [x.x, x.y, x.z]).collect();
-            joints_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, position);
-            let normals: Vec<[f32; 3]> = mesh_normals_iter.map(|p| [p.x, p.y, p.z]).collect();
-            joints_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-            let uvs: Vec<[f32; 2]> = mesh_uvs_iter.map(|p| [p.x, p.y]).collect();
-            joints_mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
-            let color: Vec<[f32; 4]> = mesh_color_iter.map(|p| [p.x, p.y, p.z, p.w]).collect();
-            joints_mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, color);
-            let indices = mesh.indices().unwrap();
-            joints_mesh.set_indices(Some(indices.clone()));
+    fn deal_transform(&self, node: &mut EntityCommands<'_, '_, '_>, name: Option<&str>) {
+        node.insert((RigidPart, AnimatedJoint));
+        if let Some(name) = name {
+            // 刚性绑定的关节自己就是一个骨骼, 见 [`Joint::skeleton`]
+            let skeleton = node.id();
+            node.insert(Joint {
+                name: name.to_string(),
+                index: 0,
+                skeleton,
+            });
+        }
+    }
+}
 
-            let r_a = Vec3::new(-8.0, 6.0, -1.5);
-            let l_a = Vec3::new(8.0, 6.0, -1.5);
-            let l_l = Vec3::new(3.5, 24.5, -1.);
-            let r_l = Vec3::new(-3.5, 24.5, -1.);
+/// 四肢的预设骨骼, 和 `assets/rigs/body1.rig.ron` 相同
+/// 和 [`RigDealers`] 一样, clone 出来的 dealer 共用生成结果
+#[derive(Debug, Clone)]
+pub struct Body1Dealers {
+    dealer: RigDealers,
+}
 
-            let r_a_local = Vec3::new(8.0, -6.0, 1.5);
-            let l_a_local = Vec3::new(-8.0, -6.0, 1.5);
-            let l_l_local = Vec3::new(-3.5, -24.5, 1.);
-            let r_l_local = Vec3::new(3.5, -24.5, 1.);
+impl Body1Dealers {
+    pub fn new() -> Self {
+        Self {
+            dealer: RigDealers::new(Self::rig()),
+        }
+    }
 
-            // let l_a_local =
-            // 这里绑定四个joint!
-            let inverse_bindposes =
-                skinned_mesh_inverse_bindposes_assets.add(SkinnedMeshInverseBindposes::from(vec![
-                    // TODO 要修改这里的值
-                    Mat4::from_translation(r_a.clone()),
-                    Mat4::from_translation(l_a.clone()),
-                    Mat4::from_translation(l_l.clone()),
-                    Mat4::from_translation(r_l.clone()),
-                ]));
+    pub fn rig() -> RigDescription {
+        preset_rig(include_str!("../assets/rigs/body1.rig.ron"))
+    }
+}
 
-            let entitiy1 = commands
-                .spawn(PbrBundle {
-                    transform: Transform::from_translation(r_a_local),
-                    mesh: mesh_assets.add(Mesh::from(shape::UVSphere {
-                        radius: 6.0,
-                        sectors: 7,
-                        stacks: 7,
-                    })),
-                    material: materials.add(StandardMaterial {
-                        base_color: Color::rgba(0.0, 1.0, 0.0, 0.5),
-                        alpha_mode: AlphaMode::Blend,
-                        ..Default::default()
-                    }),
-                    ..Default::default()
-                })
-                .insert((RightArm, AnimatedJoint))
-                .id();
-            let entitiy2 = commands
-                .spawn(PbrBundle {
-                    transform: Transform::from_translation(l_a_local),
-                    mesh: mesh_assets.add(Mesh::from(shape::UVSphere {
-                        radius: 6.0,
-                        sectors: 7,
-                        stacks: 7,
-                    })),
-                    material: materials.add(StandardMaterial {
-                        base_color: Color::rgba(0.0, 1.0, 0.0, 0.5),
-                        alpha_mode: AlphaMode::Blend,
-                        ..Default::default()
-                    }),
-                    ..Default::default()
-                })
-                .insert((LeftArm, AnimatedJoint))
-                .id();
-            let entitiy3 = commands
-                .spawn(PbrBundle {
-                    transform: Transform::from_translation(l_l_local),
-                    mesh: mesh_assets.add(Mesh::from(shape::UVSphere {
-                        radius: 6.0,
-                        sectors: 7,
-                        stacks: 7,
-                    })),
-                    material: materials.add(StandardMaterial {
-                        base_color: Color::rgba(0.0, 0.0, 1.0, 0.5),
-                        alpha_mode: AlphaMode::Blend,
-                        ..Default::default()
-                    }),
-                    ..Default::default()
-                })
-                .insert((LeftLeg, AnimatedJoint))
-                .id();
-            let entitiy4 = commands
-                .spawn(PbrBundle {
-                    transform: Transform::from_translation(r_l_local),
-                    mesh: mesh_assets.add(Mesh::from(shape::UVSphere {
-                        radius: 6.0,
-                        sectors: 7,
-                        stacks: 7,
-                    })),
-                    material: materials.add(StandardMaterial {
-                        base_color: Color::rgba(0.0, 0.0, 1.0, 0.5),
-                        alpha_mode: AlphaMode::Blend,
-                        ..Default::default()
-                    }),
-                    ..Default::default()
-                })
-                .insert((RightLeg, AnimatedJoint))
-                .id();
-            let joint_entities = vec![entitiy1, entitiy2, entitiy3, entitiy4];
-            // 这里只能使用 Joint控制物体的大小 和 位置 那么这里怎么控制他们的位置和 大小呢？
-            let ret = commands
-                .spawn(PbrBundle {
-                    mesh: mesh_assets.add(joints_mesh),
-                    material: material_handle,
-                    // 测试临时隐藏
-                    // visibility: bevy::prelude::Visibility::Hidden,
-                    ..Default::default()
-                })
-                .push_children(&joint_entities)
-                .insert(SkinnedMesh {
-                    inverse_bindposes: inverse_bindposes.clone(),
-                    joints: joint_entities,
-                })
-                .id();
-            return Some(ret);
-        }
-        None
+impl Default for Body1Dealers {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-fn mesh_color(mesh: &Mesh) -> Iter<Vec4> {
-    match mesh.attribute(Mesh::ATTRIBUTE_COLOR) {
-        Some(VertexAttributeValues::Float32x4(v)) => unsafe {
-            std::mem::transmute::<Iter<[f32; 4]>, Iter<Vec4>>(v.iter())
-        },
-        _ => [].iter(),
+impl DealWithJoints for Body1Dealers {
+    fn deal(
+        &self,
+        handle: Handle<Mesh>,
+        commands: &mut bevy::prelude::Commands,
+        mesh_assets: &mut bevy::prelude::Assets<Mesh>,
+        material_handle: Handle<bevy::prelude::StandardMaterial>,
+        skinned_mesh_inverse_bindposes_assets: &mut bevy::prelude::Assets<
+            SkinnedMeshInverseBindposes,
+        >,
+        materials: &mut Assets<StandardMaterial>,
+    ) -> Result<bevy::prelude::Entity, VoxAnimError> {
+        self.dealer.deal(
+            handle,
+            commands,
+            mesh_assets,
+            material_handle,
+            skinned_mesh_inverse_bindposes_assets,
+            materials,
+        )
+    }
+
+    fn clear_cache(&self) {
+        self.dealer.clear_cache();
     }
 }
 
+/// 两只手和身体的预设骨骼, 和 `assets/rigs/body0.rig.ron` 相同
+/// 和 [`RigDealers`] 一样, clone 出来的 dealer 共用生成结果
 #[derive(Debug, Clone)]
-pub struct Body0Dealers;
+pub struct Body0Dealers {
+    dealer: RigDealers,
+}
+
+impl Body0Dealers {
+    pub fn new() -> Self {
+        Self {
+            dealer: RigDealers::new(Self::rig()),
+        }
+    }
+
+    pub fn rig() -> RigDescription {
+        preset_rig(include_str!("../assets/rigs/body0.rig.ron"))
+    }
+}
+
+impl Default for Body0Dealers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl DealWithJoints for Body0Dealers {
     fn deal(
@@ -249,125 +187,254 @@ impl DealWithJoints for Body0Dealers {
             SkinnedMeshInverseBindposes,
         >,
         materials: &mut Assets<StandardMaterial>,
-    ) -> Option<bevy::prelude::Entity> {
-        // 这里记录 两个手 还有 一个身体的标记
-        if let Some(mesh) = mesh_assets.get(&handle) {
-            let mut joints_mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    ) -> Result<bevy::prelude::Entity, VoxAnimError> {
+        self.dealer.deal(
+            handle,
+            commands,
+            mesh_assets,
+            material_handle,
+            skinned_mesh_inverse_bindposes_assets,
+            materials,
+        )
+    }
 
-            let positions_iter = mesh_positions(mesh);
-            // 这要重重新转成数据
-            let mesh_normals_iter = mesh_normals(mesh);
-            let mesh_uvs_iter = mesh_uvs(mesh);
-            let mesh_color_iter = mesh_color(mesh);
-            // 生成 joint index 并且配置权重
-            joints_mesh.insert_attribute(
-                Mesh::ATTRIBUTE_JOINT_INDEX,
-                VertexAttributeValues::Uint16x4(vec![[0, 1, 2, 0]; mesh_len(mesh)]),
-            );
-            // 配置权限
-            let joint_weight: Vec<[f32; 4]> = positions_iter
-                .clone()
-                .map(|v3| {
-                    let Vec3 { x, y: _, z: _ } = v3;
-                    return if *x < -15.0 {
-                        [0.0, 1.0, 0.0, 0.0]
-                    } else if *x < 15.0 {
-                        [0.0, 0.0, 1.0, 0.0]
-                    } else {
-                        [1.0, 0.0, 0.0, 0.0]
-                    };
-                })
-                .collect();
-            joints_mesh.insert_attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT, joint_weight);
-            let position: Vec<[f32; 3]> = positions_iter.map(|x| [x.x, x.y, x.z]).collect();
-            joints_mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, position);
-            let normals: Vec<[f32; 3]> = mesh_normals_iter.map(|p| [p.x, p.y, p.z]).collect();
-            joints_mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-            let uvs: Vec<[f32; 2]> = mesh_uvs_iter.map(|p| [p.x, p.y]).collect();
-            joints_mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
-            let color: Vec<[f32; 4]> = mesh_color_iter.map(|p| [p.x, p.y, p.z, p.w]).collect();
-            joints_mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, color);
-            let indices = mesh.indices().unwrap();
-            joints_mesh.set_indices(Some(indices.clone()));
+    fn clear_cache(&self) {
+        self.dealer.clear_cache();
+    }
+}
 
-            let r_h = Vec3::new(-24.0, 7.0, -2.5);
-            let l_h = Vec3::new(24.0, 7.0, -2.5);
-            let b = Vec3::new(0.0, 0.0, -0.0);
+// 预设骨骼在编译时读入, 解析失败是代码错误
+fn preset_rig(source: &str) -> RigDescription {
+    ron::de::from_str(source).expect("built-in rig description")
+}
 
-            // let r_h = Vec3::ZERO;
-            // let l_h = Vec3::ZERO;
-            // let b = Vec3::ZERO;
+/// 根据 [`RigDescription`] 生成关节和权重
+/// 新的角色只需要写一个描述文件, 不需要再新建 Dealer
+/// 同一个 dealer (以及它的 clone) 对同一个模型只生成一次 mesh, 见 [`SkinCache`]
+#[derive(Debug, Clone)]
+pub struct RigDealers {
+    pub rig: RigDescription,
+    /// vox 文件的调色板, [`WeightMode::Palette`] 需要用它从顶点色找回调色板序号
+    pub palette: Vec<[f32; 4]>,
+    pub cache: SkinCache,
+}
 
-            let r_h_local = Vec3::new(24.0, -7.0, 2.5);
-            let l_h_local = Vec3::new(-24.0, -7.0, 2.5);
-            let b_local = Vec3::ZERO;
+impl RigDealers {
+    /// 每次调用都会新建一个缓存
+    /// 多个角色要共用生成结果时 clone 同一个 dealer, 或者用 [`Self::with_cache`] 传入同一个缓存
+    /// `.voxrig` 生成的角色都 clone 资源中的 dealer, 已经是共用的
+    pub fn new(rig: RigDescription) -> Self {
+        Self {
+            rig,
+            palette: Vec::new(),
+            cache: SkinCache::default(),
+        }
+    }
 
-            // 这里绑定四个joint!
-            let inverse_bindposes =
-                skinned_mesh_inverse_bindposes_assets.add(SkinnedMeshInverseBindposes::from(vec![
-                    Mat4::from_translation(r_h.clone()),
-                    Mat4::from_translation(l_h.clone()),
-                    Mat4::from_translation(b.clone()),
-                ]));
-            let entitiy1 = commands
-                .spawn(PbrBundle {
-                    transform: Transform::from_translation(r_h_local),
-                    mesh: mesh_assets.add(Mesh::from(shape::UVSphere {
-                        radius: 6.0,
-                        sectors: 7,
-                        stacks: 7,
-                    })),
-                    material: materials.add(StandardMaterial {
-                        base_color: Color::rgba(1.0, 0.0, 0.0, 0.5),
-                        alpha_mode: AlphaMode::Blend,
-                        ..Default::default()
-                    }),
-                    ..Default::default()
-                })
-                .insert((RightHand, AnimatedJoint))
-                .id();
-            let entitiy2 = commands
-                .spawn(PbrBundle {
-                    transform: Transform::from_translation(l_h_local),
-                    mesh: mesh_assets.add(Mesh::from(shape::UVSphere {
-                        radius: 6.0,
-                        sectors: 7,
-                        stacks: 7,
-                    })),
-                    material: materials.add(StandardMaterial {
-                        base_color: Color::rgba(1.0, 0.0, 0.0, 0.5),
-                        alpha_mode: AlphaMode::Blend,
-                        ..Default::default()
-                    }),
-                    ..Default::default()
-                })
-                .insert((LeftHand, AnimatedJoint))
-                .id();
-            let entitiy3 = commands
-                .spawn((
-                    Body,
-                    AnimatedJoint,
-                    TransformBundle {
-                        local: Transform::from_translation(b_local),
-                        ..Default::default()
-                    },
-                ))
-                .id();
-            let joint_entities = vec![entitiy1, entitiy2, entitiy3];
-            let ret = commands
-                .spawn(PbrBundle {
-                    mesh: mesh_assets.add(joints_mesh),
-                    material: material_handle,
-                    ..Default::default()
-                })
-                .push_children(&joint_entities)
-                .insert(SkinnedMesh {
-                    inverse_bindposes: inverse_bindposes.clone(),
-                    joints: joint_entities,
-                })
-                .id();
-            return Some(ret);
+    /// 和其他 dealer 共用缓存, 缓存按源 mesh 区分, 只应该在骨骼相同的 dealer 之间共用
+    pub fn with_cache(mut self, cache: SkinCache) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    pub fn with_palette(mut self, palette: &[dot_vox::Color]) -> Self {
+        self.palette = vox_palette_colors(palette);
+        self
+    }
+}
+
+impl DealWithJoints for RigDealers {
+    fn deal(
+        &self,
+        handle: Handle<Mesh>,
+        commands: &mut bevy::prelude::Commands,
+        mesh_assets: &mut bevy::prelude::Assets<Mesh>,
+        material_handle: Handle<bevy::prelude::StandardMaterial>,
+        skinned_mesh_inverse_bindposes_assets: &mut bevy::prelude::Assets<
+            SkinnedMeshInverseBindposes,
+        >,
+        materials: &mut Assets<StandardMaterial>,
+    ) -> Result<bevy::prelude::Entity, VoxAnimError> {
+        let skin = match self.cache.get(&handle) {
+            // 权重在生成缓存时已经检查过并输出了 SkinReport, 这里只确认 bindposes 还在
+            Some(skin) => {
+                let bindposes = skinned_mesh_inverse_bindposes_assets
+                    .get(&skin.inverse_bindposes)
+                    .map_or(0, |bindposes| bindposes.len());
+                if bindposes != self.rig.joints.len() {
+                    return Err(SkinError::BindposeCountMismatch {
+                        bindposes,
+                        joints: self.rig.joints.len(),
+                    }
+                    .into());
+                }
+                skin
+            }
+            None => {
+                let skin = self.build_skin(
+                    &handle,
+                    mesh_assets,
+                    skinned_mesh_inverse_bindposes_assets,
+                    materials,
+                )?;
+                self.cache.insert(&handle, skin.clone());
+                skin
+            }
+        };
+
+        let ret = commands.spawn_empty().id();
+        let skeleton =
+            self.rig
+                .skeleton_builder()
+                .spawn_joints(commands, ret, skin.inverse_bindposes.clone());
+        for ((joint, entity), sphere) in self
+            .rig
+            .joints
+            .iter()
+            .zip(skeleton.joints.iter())
+            .zip(skin.spheres.iter())
+        {
+            let mut joint_entity = commands.entity(*entity);
+            if let Some(sphere) = sphere {
+                joint_entity.insert(sphere.clone());
+            }
+            joint_entity.insert(AnimatedJoint);
+            if let Some(marker) = joint.marker {
+                marker.insert(&mut joint_entity);
+            }
+        }
+        for socket in self.rig.sockets.iter() {
+            // validate 已经检查过关节名字
+            if let Some(index) = self.rig.joint_index(&socket.joint) {
+                spawn_socket(commands, skeleton.joints[index], socket);
+            }
+        }
+        commands
+            .entity(ret)
+            .insert(PbrBundle {
+                mesh: skin.mesh,
+                material: material_handle,
+                ..Default::default()
+            })
+            .push_children(&skeleton.roots)
+            .insert(skeleton.skinned_mesh());
+        Ok(ret)
+    }
+
+    fn clear_cache(&self) {
+        self.cache.clear();
+    }
+}
+
+impl RigDealers {
+    // 计算权重, 生成可以共用的 mesh, bindposes 和关节球
+    fn build_skin(
+        &self,
+        handle: &Handle<Mesh>,
+        mesh_assets: &mut Assets<Mesh>,
+        skinned_mesh_inverse_bindposes_assets: &mut Assets<SkinnedMeshInverseBindposes>,
+        materials: &mut Assets<StandardMaterial>,
+    ) -> Result<CachedSkin, VoxAnimError> {
+        self.rig.validate()?;
+        let mesh = mesh_assets
+            .get(handle)
+            .ok_or_else(|| VoxAnimError::mesh_not_loaded(handle))?;
+        let builder = self.rig.skeleton_builder();
+        let joint_positions: Vec<Vec3> = builder
+            .rest_matrices()
+            .iter()
+            .map(|matrix| matrix.w_axis.truncate())
+            .collect();
+        let palette_indices: Vec<Option<u8>> = match &self.rig.weight_mode {
+            WeightMode::Palette(weights) => {
+                // 没有调色板时所有顶点都会落到区域或者默认关节上
+                if self.palette.is_empty() {
+                    return Err(VoxAnimError::MissingPalette);
+                }
+                for (first, duplicate) in duplicate_colors(&self.palette) {
+                    if weights.joints.contains_key(&duplicate) {
+                        warn!(
+                            "palette index {} has the same color as {}, its vertices use the joint of {}",
+                            duplicate, first, first
+                        );
+                    }
+                }
+                vertex_colors(mesh)
+                    .iter()
+                    .map(|color| palette_index(*color, &self.palette))
+                    .collect()
+            }
+            _ => Vec::new(),
+        };
+        let positions = vertex_positions(mesh);
+        if positions.is_empty() {
+            return Err(VoxAnimError::MissingPositions(format!("{:?}", handle.id())));
         }
-        None
+        // bevy_vox_mesh 生成的 mesh 都有索引, 没有时说明不是 vox 模型
+        if mesh.indices().is_none() {
+            return Err(VoxAnimError::MissingIndices(format!("{:?}", handle.id())));
+        }
+        let (joint_index, mut joint_weight): (Vec<[u16; 4]>, Vec<[f32; 4]>) = positions
+            .iter()
+            .enumerate()
+            .map(|(i, v3)| {
+                let palette_index = palette_indices.get(i).copied().flatten();
+                self.rig
+                    .vertex_weights(*v3, palette_index, &joint_positions)
+            })
+            .unzip();
+        // 必须在 skinned_mesh_from 之前检查, insert_attribute 会把全为 0 的权重改掉
+        check_weights(&joint_index, &mut joint_weight, builder.len())?;
+        let joints_mesh = skinned_mesh_from(mesh, joint_index, joint_weight);
+        let inverse_bindposes = skinned_mesh_inverse_bindposes_assets.add(
+            SkinnedMeshInverseBindposes::from(builder.inverse_bindposes()),
+        );
+        let spheres = self
+            .rig
+            .joints
+            .iter()
+            .map(|joint| {
+                joint.sphere.as_ref().map(|sphere| {
+                    joint_sphere_assets(sphere.radius, sphere.color(), mesh_assets, materials)
+                })
+            })
+            .collect();
+        Ok(CachedSkin {
+            mesh: mesh_assets.add(joints_mesh),
+            inverse_bindposes,
+            spheres,
+        })
     }
 }
+
+// 检查权重, 有问题时输出警告并返回第一个错误
+// 在生成任何 entity 之前检查, 出错时不会留下一半的骨骼
+fn check_weights(
+    indices: &[[u16; 4]],
+    weights: &mut [[f32; 4]],
+    joint_count: usize,
+) -> Result<(), VoxAnimError> {
+    validate_weights(indices, weights, joint_count).log_result("skinned mesh")?;
+    Ok(())
+}
+
+// 关节上半透明的球, 方便编辑时选中
+fn joint_sphere_assets(
+    radius: f32,
+    color: Color,
+    mesh_assets: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+) -> (Handle<Mesh>, Handle<StandardMaterial>) {
+    (
+        mesh_assets.add(Mesh::from(shape::UVSphere {
+            radius,
+            sectors: 7,
+            stacks: 7,
+        })),
+        materials.add(StandardMaterial {
+            base_color: color,
+            alpha_mode: AlphaMode::Blend,
+            ..Default::default()
+        }),
+    )
+}
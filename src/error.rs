@@ -0,0 +1,34 @@
+// 生成角色时的错误
+
+use bevy::prelude::{Handle, Mesh};
+use thiserror::Error;
+
+use crate::validation::SkinError;
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum VoxAnimError {
+    #[error("mesh {0} is not loaded")]
+    MeshNotLoaded(String),
+    #[error("mesh {0} has no vertex positions")]
+    MissingPositions(String),
+    #[error("mesh {0} has no vertex indices")]
+    MissingIndices(String),
+    #[error("scene node {0} does not exist")]
+    BadNodeIndex(u32),
+    #[error("scene node {0} is not matched by any dealer")]
+    UnmatchedNode(String),
+    #[error("rig has no joints")]
+    EmptyRig,
+    #[error("rig uses palette weights but the dealer has no palette")]
+    MissingPalette,
+    #[error("joint {0} does not exist")]
+    MissingJoint(String),
+    #[error("invalid skin weights: {0}")]
+    Skin(#[from] SkinError),
+}
+
+impl VoxAnimError {
+    pub fn mesh_not_loaded(handle: &Handle<Mesh>) -> Self {
+        Self::MeshNotLoaded(format!("{:?}", handle.id()))
+    }
+}
@@ -0,0 +1,100 @@
+// 角色根节点上的关节表
+// 生成时建一次, 层级变化时重建, 之后按名字查找关节不再需要遍历子节点
+
+use bevy::{
+    log::warn,
+    prelude::{
+        Added, App, Changed, Children, Component, DetectChanges, Entity, HierarchyQueryExt, Or,
+        Parent, Plugin, Query, RemovedComponents, Update,
+    },
+    utils::HashMap,
+};
+
+use crate::{sockets::CharacterHierarchy, types::Joint};
+
+/// 放在角色根节点上, 记录所有子孙节点中的 [`Joint`]
+/// 插入一个空的 `JointMap` 就会在下一帧自动填充
+#[derive(Debug, Clone, Default, Component)]
+pub struct JointMap {
+    joints: HashMap<String, Entity>,
+}
+
+impl JointMap {
+    pub fn get(&self, name: &str) -> Option<Entity> {
+        self.joints.get(name).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Entity)> {
+        self.joints.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.joints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.joints.is_empty()
+    }
+
+    /// 重新遍历 `root` 下的所有关节, 挂载的物体上的关节不算
+    pub fn rebuild(
+        &mut self,
+        root: Entity,
+        hierarchy: &CharacterHierarchy,
+        joint_query: &Query<&Joint>,
+    ) {
+        self.joints.clear();
+        for entity in hierarchy.descendants(root).into_iter().skip(1) {
+            let Ok(joint) = joint_query.get(entity) else {
+                continue;
+            };
+            if self.joints.contains_key(&joint.name) {
+                warn!(
+                    "duplicate joint name {}, only the first one is used",
+                    joint.name
+                );
+            } else {
+                self.joints.insert(joint.name.clone(), entity);
+            }
+        }
+    }
+}
+
+pub struct JointMapPlugin;
+
+impl Plugin for JointMapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, sync_joint_maps);
+    }
+}
+
+type ChangedHierarchy<'w, 's> = Query<'w, 's, Entity, Or<(Changed<Children>, Added<Joint>)>>;
+
+// 新加的 JointMap 或者层级有变化时重建
+// 只重建变化的节点所在的角色
+pub(crate) fn sync_joint_maps(
+    mut maps: Query<(Entity, &mut JointMap)>,
+    changed: ChangedHierarchy,
+    mut removed_joints: RemovedComponents<Joint>,
+    parents: Query<&Parent>,
+    hierarchy: CharacterHierarchy,
+    joint_query: Query<&Joint>,
+) {
+    // 删掉的关节可能已经没有父节点, 按关节表里记录的 entity 查找
+    let removed: Vec<Entity> = removed_joints.iter().collect();
+    let mut dirty: Vec<Entity> = changed
+        .iter()
+        .flat_map(|entity| std::iter::once(entity).chain(parents.iter_ancestors(entity)))
+        .filter(|ancestor| maps.contains(*ancestor))
+        .collect();
+    dirty.sort();
+    dirty.dedup();
+    for (root, mut map) in maps.iter_mut() {
+        let rebuild = map.is_added()
+            || dirty.binary_search(&root).is_ok()
+            || map.iter().any(|(_, joint)| removed.contains(joint));
+        if rebuild {
+            map.rebuild(root, &hierarchy, &joint_query);
+        }
+    }
+}
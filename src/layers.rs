@@ -0,0 +1,323 @@
+// vox 图层
+// 每个 transform 节点都带有 LayerData, 运行时可以按图层显示或者隐藏, 用于装备, 调试标记或者 LOD
+
+use bevy::{
+    log::warn,
+    prelude::{
+        App, Changed, Color, Component, Entity, Event, EventReader, IntoSystemConfigs, Or, Plugin,
+        PostUpdate, Query, Update, Visibility,
+    },
+    render::view::VisibilitySystems,
+    utils::HashMap,
+};
+use bevy_vox_mesh::vox_scene_info::LayerData;
+
+use crate::{character::VoxCharacter, sockets::CharacterHierarchy, variants::VariantState};
+
+/// 节点被隐藏的原因, 任何一个成立时节点隐藏
+/// 图层和互斥部件都通过它修改显示状态, 不会互相覆盖
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Component)]
+pub struct NodeVisibility {
+    pub layer_hidden: bool,
+    pub variant_hidden: bool,
+}
+
+impl NodeVisibility {
+    pub fn visibility(&self) -> Visibility {
+        if self.layer_hidden || self.variant_hidden {
+            Visibility::Hidden
+        } else {
+            Visibility::Inherited
+        }
+    }
+}
+
+/// vox 文件中的一个图层
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoxLayer {
+    pub id: u32,
+    pub name: Option<String>,
+    pub color: Option<Color>,
+    pub hidden: bool,
+}
+
+/// 放在角色根节点上, 记录图层信息和当前的显示状态
+/// 修改之后下一帧会更新所有带有对应 [`LayerData`] 的节点
+/// 属于互斥部件组的节点只由 [`VariantState`] 控制, MagicaVoxel 中通常会隐藏其他表情所在的图层
+#[derive(Debug, Clone, Default, Component)]
+pub struct VoxLayers {
+    layers: Vec<VoxLayer>,
+}
+
+impl VoxLayers {
+    /// 从 vox 文件读取图层名字和颜色, 序号就是图层 id
+    pub fn from_vox(layers: &[dot_vox::Layer]) -> Self {
+        Self {
+            layers: layers
+                .iter()
+                .enumerate()
+                .map(|(id, layer)| VoxLayer {
+                    id: id as u32,
+                    name: layer.attributes.get("_name").cloned(),
+                    color: layer.attributes.get("_color").and_then(|c| parse_color(c)),
+                    hidden: layer.attributes.get("_hidden").is_some_and(|h| h == "1"),
+                })
+                .collect(),
+        }
+    }
+
+    /// 只有 `VoxSceneInfo::layer_map` 时没有名字和颜色
+    pub fn from_layer_map(layer_map: &HashMap<u32, bool>) -> Self {
+        let mut layers: Vec<VoxLayer> = layer_map
+            .iter()
+            .map(|(id, hidden)| VoxLayer {
+                id: *id,
+                name: None,
+                color: None,
+                hidden: *hidden,
+            })
+            .collect();
+        layers.sort_by_key(|layer| layer.id);
+        Self { layers }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &VoxLayer> {
+        self.layers.iter()
+    }
+
+    pub fn get(&self, id: u32) -> Option<&VoxLayer> {
+        self.layers.iter().find(|layer| layer.id == id)
+    }
+
+    /// 按名字或者 id 找到图层 id
+    pub fn resolve(&self, layer: &LayerRef) -> Option<u32> {
+        match layer {
+            LayerRef::Id(id) => self.get(*id).map(|layer| layer.id),
+            LayerRef::Name(name) => self
+                .layers
+                .iter()
+                .find(|layer| layer.name.as_ref() == Some(name))
+                .map(|layer| layer.id),
+        }
+    }
+
+    /// 不在列表中的图层都当作显示
+    pub fn is_hidden(&self, id: u32) -> bool {
+        self.get(id).is_some_and(|layer| layer.hidden)
+    }
+
+    pub fn set_hidden(&mut self, id: u32, hidden: bool) {
+        if let Some(layer) = self.layers.iter_mut().find(|layer| layer.id == id) {
+            layer.hidden = hidden;
+        }
+    }
+
+    /// 重新读取图层之后保留 `old` 中同一个 id 的显示状态
+    pub fn keep_hidden(&mut self, old: &VoxLayers) {
+        for layer in self.layers.iter_mut() {
+            if let Some(old) = old.get(layer.id) {
+                layer.hidden = old.hidden;
+            }
+        }
+    }
+
+    /// 只显示 `id`, 隐藏其他所有图层
+    pub fn isolate(&mut self, id: u32) {
+        for layer in self.layers.iter_mut() {
+            layer.hidden = layer.id != id;
+        }
+    }
+}
+
+// MagicaVoxel 中图层颜色是 "r g b"
+fn parse_color(value: &str) -> Option<Color> {
+    let channels: Vec<u8> = value
+        .split_whitespace()
+        .filter_map(|c| c.parse().ok())
+        .collect();
+    match channels[..] {
+        [r, g, b] => Some(Color::rgb_u8(r, g, b)),
+        _ => None,
+    }
+}
+
+/// 按 id 或者名字指定图层
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LayerRef {
+    Id(u32),
+    Name(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerAction {
+    Show,
+    Hide,
+    /// 只显示这个图层
+    Isolate,
+}
+
+/// 修改一个角色的图层显示, 只影响这个角色下的节点
+#[derive(Debug, Clone, Event)]
+pub struct SetLayerVisibility {
+    pub character: Entity,
+    pub layer: LayerRef,
+    pub action: LayerAction,
+}
+
+/// 由 [`crate::character::VoxCharacterPlugin`] 添加
+pub struct VoxLayersPlugin;
+
+impl Plugin for VoxLayersPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SetLayerVisibility>()
+            .add_systems(Update, (apply_layer_events, sync_layer_visibility).chain())
+            // 图层和部件都在 Update 中修改, 统一在可见性传播之前生效
+            .add_systems(
+                PostUpdate,
+                apply_node_visibility.before(VisibilitySystems::VisibilityPropagate),
+            );
+    }
+}
+
+fn apply_layer_events(
+    mut events: EventReader<SetLayerVisibility>,
+    mut characters: Query<&mut VoxLayers>,
+) {
+    for event in events.iter() {
+        let Ok(mut layers) = characters.get_mut(event.character) else {
+            warn!("{:?} has no VoxLayers", event.character);
+            continue;
+        };
+        let Some(id) = layers.resolve(&event.layer) else {
+            warn!("{:?}: layer {:?} not found", event.character, event.layer);
+            continue;
+        };
+        match event.action {
+            LayerAction::Show => layers.set_hidden(id, false),
+            LayerAction::Hide => layers.set_hidden(id, true),
+            LayerAction::Isolate => layers.isolate(id),
+        }
+    }
+}
+
+type ChangedLayers<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static VoxLayers,
+        Option<&'static VoxCharacter>,
+        Option<&'static VariantState>,
+    ),
+    Or<(Changed<VoxLayers>, Changed<VoxCharacter>)>,
+>;
+
+// 图层变化或者角色重新生成之后更新角色下所有的节点
+fn sync_layer_visibility(
+    characters: ChangedLayers,
+    hierarchy: CharacterHierarchy,
+    mut nodes: Query<(&LayerData, &mut NodeVisibility)>,
+) {
+    for (character, layers, parts, variants) in characters.iter() {
+        // 互斥部件的根节点
+        let variant_roots: Vec<Entity> = match (parts, variants) {
+            (Some(parts), Some(variants)) => variants
+                .groups()
+                .flat_map(|(_, values)| values.iter())
+                .filter_map(|value| parts.parts.get(value))
+                .flatten()
+                .copied()
+                .collect(),
+            _ => Vec::new(),
+        };
+        let mut stack = vec![(character, false)];
+        while let Some((entity, in_variant)) = stack.pop() {
+            let in_variant = in_variant || variant_roots.contains(&entity);
+            if let Ok((layer, mut visibility)) = nodes.get_mut(entity) {
+                let hidden = !in_variant && layers.is_hidden(layer.0);
+                if visibility.layer_hidden != hidden {
+                    visibility.layer_hidden = hidden;
+                }
+            }
+            stack.extend(hierarchy.children(entity).map(|child| (child, in_variant)));
+        }
+    }
+}
+
+fn apply_node_visibility(
+    mut nodes: Query<(&NodeVisibility, &mut Visibility), Changed<NodeVisibility>>,
+) {
+    for (node, mut visibility) in nodes.iter_mut() {
+        let target = node.visibility();
+        if *visibility != target {
+            *visibility = target;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::variants::{SetVariant, VoxVariantsPlugin};
+    use bevy::prelude::BuildWorldChildren;
+
+    fn hidden_layer(id: u32) -> VoxLayers {
+        VoxLayers::from_layer_map(&HashMap::from_iter([(0, false), (id, true)]))
+    }
+
+    #[test]
+    fn active_variant_ignores_hidden_layer() {
+        let mut app = App::new();
+        app.add_plugins((VoxLayersPlugin, VoxVariantsPlugin));
+        // face1 和它的子节点都在隐藏的图层 1 上, 没有分组的 hat 也在图层 1 上
+        let spawn_node = |app: &mut App, layer: u32| {
+            app.world
+                .spawn((
+                    LayerData(layer),
+                    NodeVisibility {
+                        layer_hidden: layer == 1,
+                        ..Default::default()
+                    },
+                    Visibility::Inherited,
+                ))
+                .id()
+        };
+        let face0 = spawn_node(&mut app, 0);
+        let face1 = spawn_node(&mut app, 1);
+        let face1_child = spawn_node(&mut app, 1);
+        let hat = spawn_node(&mut app, 1);
+        app.world.entity_mut(face1).add_child(face1_child);
+        let groups = HashMap::from_iter([(
+            "face".to_string(),
+            vec!["face0".to_string(), "face1".to_string()],
+        )]);
+        let parts = HashMap::from_iter([
+            ("face0".to_string(), vec![face0]),
+            ("face1".to_string(), vec![face1]),
+            ("hat".to_string(), vec![hat]),
+        ]);
+        let character = app
+            .world
+            .spawn((
+                hidden_layer(1),
+                VariantState::new(groups),
+                VoxCharacter { parts },
+            ))
+            .push_children(&[face0, face1, hat])
+            .id();
+        app.update();
+
+        app.world.send_event(SetVariant {
+            character,
+            group: "face".to_string(),
+            value: "face1".to_string(),
+        });
+        app.update();
+
+        let visibility = |app: &App, entity: Entity| *app.world.get::<Visibility>(entity).unwrap();
+        assert_eq!(visibility(&app, face0), Visibility::Hidden);
+        assert_eq!(visibility(&app, face1), Visibility::Inherited);
+        assert_eq!(visibility(&app, face1_child), Visibility::Inherited);
+        assert_eq!(visibility(&app, hat), Visibility::Hidden);
+    }
+}
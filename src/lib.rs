@@ -1,19 +1,45 @@
+use auto_rig::is_joint_node;
 use bevy::{
+    ecs::system::EntityCommands,
     prelude::{
-        AssetServer, Assets, BuildChildren, Commands, ComputedVisibility, Entity, GlobalTransform,
-        Handle, Mesh, Name, StandardMaterial, Transform, Visibility,
+        AssetServer, Assets, BuildChildren, Commands, ComputedVisibility, DespawnRecursiveExt,
+        Entity, GlobalTransform, Handle, Mesh, Name, StandardMaterial, Transform,
     },
     render::mesh::skinning::SkinnedMeshInverseBindposes,
     utils::HashMap,
 };
 use bevy_vox_mesh::vox_scene_info::{LayerData, VoxSceneInfo};
+use dealers::CommonDealers;
 use dot_vox::SceneNode;
+use error::VoxAnimError;
+use layers::NodeVisibility;
+use node_match::{match_scene_nodes, FallbackPolicy, MatchedNode, NodeMatcher};
+use vox_animation::{frame_index, ModelKeyframe, VoxKeyframes};
+use vox_transform::node_transform;
 
 // 制作和使用 vox 作为动画的工具
+pub mod auto_rig;
+pub mod character;
 pub mod dealers;
+pub mod error;
+pub mod joint_map;
+pub mod layers;
+pub mod manifest;
 pub mod mesh_helper;
+pub mod node_match;
+pub mod palette;
 pub mod pose;
+pub mod reload;
+pub mod rig;
+pub mod skeleton;
+pub mod skin_cache;
+pub mod sockets;
 pub mod types;
+pub mod validation;
+pub mod variants;
+pub mod vox_animation;
+pub mod vox_transform;
+pub mod weights;
 pub trait DealWithJoints: Send + Sync + 'static {
     fn deal(
         &self,
@@ -23,69 +49,100 @@ pub trait DealWithJoints: Send + Sync + 'static {
         material_handle: Handle<StandardMaterial>,
         skinned_mesh_inverse_bindposes_assets: &mut Assets<SkinnedMeshInverseBindposes>,
         materials: &mut Assets<StandardMaterial>,
-    ) -> Option<Entity>;
+    ) -> Result<Entity, VoxAnimError>;
+
+    /// 直接包住模型的 Transform 节点生成之后调用, 默认什么都不做
+    /// 刚性绑定时把这个节点当作关节, 见 [`dealers::RigidPartDealers`]
+    fn deal_transform(&self, _node: &mut EntityCommands<'_, '_, '_>, _name: Option<&str>) {}
+
+    /// 源模型修改之后调用, 丢掉缓存的生成结果, 默认什么都不做
+    fn clear_cache(&self) {}
 }
 
 pub struct DealerHolder {}
 
+/// 按 `perpare_map` 生成场景中的节点
+/// key 可以是节点名字, 路径 (`character/body/arm`) 或者通配符 (`face*`), 见 [`node_match`]
+/// 没有被任何 key 覆盖的模型按 `fallback` 处理, 生成的 entity 以节点路径作为 key
+/// 返回每个 key 匹配到的所有节点生成的 entity, 出错时已经生成的 entity 都会被删除
+#[allow(clippy::too_many_arguments)]
 pub fn perpare_player_data(
-    base_id: &'static str,
+    base_id: &str,
     vox_mate_data: VoxSceneInfo,
     commands: &mut Commands,
     asset_server: &AssetServer,
     material_handle: Handle<StandardMaterial>,
     mesh_assets: &mut Assets<Mesh>,
     skinned_mesh_inverse_bindposes_assets: &mut Assets<SkinnedMeshInverseBindposes>,
-    perpare_map: HashMap<String, Box<dyn DealWithJoints>>,
+    perpare_map: &HashMap<String, Box<dyn DealWithJoints>>,
+    fallback: FallbackPolicy,
     materials: &mut Assets<StandardMaterial>,
-) -> HashMap<String, Entity> {
-    let mut result: HashMap<String, Entity> = HashMap::new();
-    for scene_node in vox_mate_data.scenes.iter() {
-        match scene_node {
-            SceneNode::Transform {
-                attributes,
-                frames: _,
-                child: _,
-                layer_id: _,
-            } => {
-                if let Some(name) = attributes.get("_name") {
-                    if let Some(dealer) = perpare_map.get(name) {
-                        let ret = deal_scene_node(
-                            base_id,
-                            commands,
-                            asset_server,
-                            scene_node,
-                            &vox_mate_data.scenes,
-                            material_handle.clone(),
-                            mesh_assets,
-                            &vox_mate_data.layer_map,
-                            skinned_mesh_inverse_bindposes_assets,
-                            materials,
-                            dealer,
-                        );
-                        result.insert(name.clone(), ret[0]);
+) -> Result<HashMap<String, Vec<Entity>>, VoxAnimError> {
+    let mut result: HashMap<String, Vec<Entity>> = HashMap::new();
+    let matcher = NodeMatcher::new(perpare_map.keys());
+    let common_dealer: Box<dyn DealWithJoints> = Box::new(CommonDealers);
+    for matched in match_scene_nodes(&vox_mate_data.scenes, &matcher) {
+        let (key, index, parent, dealer) = match matched {
+            MatchedNode::Part { key, index, parent } => {
+                (key.to_string(), index, parent, &perpare_map[key])
+            }
+            MatchedNode::Unmatched {
+                name,
+                index,
+                parent,
+            } => match fallback {
+                FallbackPolicy::Skip => continue,
+                FallbackPolicy::Common => (name, index, parent, &common_dealer),
+                FallbackPolicy::Error => {
+                    for entities in result.values() {
+                        despawn_all(commands, entities);
                     }
+                    return Err(VoxAnimError::UnmatchedNode(name));
                 }
+            },
+        };
+        let ret = deal_scene_node(
+            base_id,
+            commands,
+            asset_server,
+            &vox_mate_data.scenes[index],
+            parent,
+            &vox_mate_data.scenes,
+            material_handle.clone(),
+            mesh_assets,
+            &vox_mate_data.layer_map,
+            skinned_mesh_inverse_bindposes_assets,
+            materials,
+            dealer.as_ref(),
+        );
+        match ret {
+            Ok(ret) => result.entry(key).or_default().extend(ret),
+            Err(err) => {
+                for entities in result.values() {
+                    despawn_all(commands, entities);
+                }
+                return Err(err);
             }
-            _ => {}
         }
     }
-    result
+    Ok(result)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn deal_scene_node(
-    base_id: &'static str,
+    base_id: &str,
     commands: &mut Commands,
     asset_server: &AssetServer,
     scene_node: &SceneNode,
+    parent: Transform,
     scenes_tree: &Vec<SceneNode>,
     material_handle: Handle<StandardMaterial>,
     mesh_assets: &mut Assets<Mesh>,
     layer_map: &HashMap<u32, bool>,
     skinned_mesh_inverse_bindposes_assets: &mut Assets<SkinnedMeshInverseBindposes>,
     materials: &mut Assets<StandardMaterial>,
-    deal_with_joints: &Box<dyn DealWithJoints>,
-) -> Vec<Entity> {
+    deal_with_joints: &dyn DealWithJoints,
+) -> Result<Vec<Entity>, VoxAnimError> {
     let mut result: Vec<Entity> = Vec::new();
     match scene_node {
         SceneNode::Transform {
@@ -94,51 +151,66 @@ fn deal_scene_node(
             child,
             layer_id,
         } => {
+            // 关节节点只用来读取骨骼, 下面的标记模型不生成
+            if attributes
+                .get("_name")
+                .is_some_and(|name| is_joint_node(name))
+            {
+                return Ok(result);
+            }
             // 标记一下当前数据？
-            let mut node = commands.spawn(LayerData(layer_id.clone()));
+            let mut node = commands.spawn(LayerData(*layer_id));
             if let Some(name) = attributes.get("_name") {
                 node.insert(Name::new(name.to_owned()));
             }
-            for frame in frames.iter() {
-                // TODO: Support Other Types
-                if let Some(pos) = frame.position() {
-                    node.insert(Transform::from_xyz(
-                        pos.x as f32,
-                        pos.y as f32,
-                        pos.z as f32,
-                    ));
-                }
+            if let Some(SceneNode::Shape { .. }) = scenes_tree.get(*child as usize) {
+                deal_with_joints
+                    .deal_transform(&mut node, attributes.get("_name").map(|name| name.as_str()));
+            }
+            // 静止姿态使用第一帧, 包括旋转和镜像
+            // 单独生成的嵌套节点没有祖先 entity, 祖先的静止姿态直接乘上来
+            let transform = parent * node_transform(frames);
+            // 多帧时记录关键帧, 由 VoxAnimationPlayer 播放
+            if let Some(keyframes) = VoxKeyframes::from_frames(frames) {
+                node.insert(keyframes.with_parent(parent));
             }
 
-            let children = deal_scene_node(
-                base_id,
-                node.commands(),
-                asset_server,
-                &scenes_tree[child.clone() as usize],
-                scenes_tree,
-                material_handle.clone(),
-                mesh_assets,
-                layer_map,
-                skinned_mesh_inverse_bindposes_assets,
-                materials,
-                deal_with_joints,
-            );
-            node.push_children(&children);
-
-            let visibilty = if let Some(hidden) = layer_map.get(layer_id) {
-                if *hidden {
-                    Visibility::Hidden
-                } else {
-                    Visibility::Inherited
+            let children = scene_node_at(scenes_tree, *child).and_then(|child_node| {
+                deal_scene_node(
+                    base_id,
+                    node.commands(),
+                    asset_server,
+                    child_node,
+                    Transform::IDENTITY,
+                    scenes_tree,
+                    material_handle.clone(),
+                    mesh_assets,
+                    layer_map,
+                    skinned_mesh_inverse_bindposes_assets,
+                    materials,
+                    deal_with_joints,
+                )
+            });
+            match children {
+                Ok(children) => {
+                    node.push_children(&children);
+                }
+                Err(err) => {
+                    node.despawn_recursive();
+                    return Err(err);
                 }
-            } else {
-                Visibility::Inherited
+            }
+
+            let node_visibility = NodeVisibility {
+                layer_hidden: layer_map.get(layer_id).copied().unwrap_or(false),
+                ..Default::default()
             };
             node.insert((
-                visibilty,
+                node_visibility,
+                node_visibility.visibility(),
                 ComputedVisibility::HIDDEN,
-                Transform::IDENTITY,
-                GlobalTransform::IDENTITY,
+                transform,
+                GlobalTransform::from(transform),
             ));
             result.push(node.id());
         }
@@ -148,20 +220,29 @@ fn deal_scene_node(
         } => {
             // 获取一组数据
             for ch_key in children {
-                let children = deal_scene_node(
-                    base_id,
-                    commands,
-                    asset_server,
-                    &scenes_tree[ch_key.clone() as usize],
-                    scenes_tree,
-                    material_handle.clone(),
-                    mesh_assets,
-                    layer_map,
-                    skinned_mesh_inverse_bindposes_assets,
-                    materials,
-                    deal_with_joints,
-                );
-                result.extend(children);
+                let children = scene_node_at(scenes_tree, *ch_key).and_then(|child_node| {
+                    deal_scene_node(
+                        base_id,
+                        commands,
+                        asset_server,
+                        child_node,
+                        Transform::IDENTITY,
+                        scenes_tree,
+                        material_handle.clone(),
+                        mesh_assets,
+                        layer_map,
+                        skinned_mesh_inverse_bindposes_assets,
+                        materials,
+                        deal_with_joints,
+                    )
+                });
+                match children {
+                    Ok(children) => result.extend(children),
+                    Err(err) => {
+                        despawn_all(commands, &result);
+                        return Err(err);
+                    }
+                }
             }
         }
         SceneNode::Shape {
@@ -171,14 +252,17 @@ fn deal_scene_node(
             // 这里生成单个的entity
             for shape in models {
                 let key = if shape.model_id == 0 {
-                    format!("{}", base_id,)
+                    base_id.to_string()
                 } else {
                     format!("{}#model{}", base_id, shape.model_id)
                 };
                 let handle: Handle<Mesh> = asset_server.get_handle(key.clone());
-                println!("{}-{:?}", key, asset_server.get_load_state(handle.clone()));
+                if !mesh_assets.contains(&handle) {
+                    despawn_all(commands, &result);
+                    return Err(VoxAnimError::MeshNotLoaded(key));
+                }
 
-                if let Some(entity) = deal_with_joints.deal(
+                match deal_with_joints.deal(
                     handle.clone(),
                     commands,
                     mesh_assets,
@@ -186,10 +270,35 @@ fn deal_scene_node(
                     skinned_mesh_inverse_bindposes_assets,
                     materials,
                 ) {
-                    result.push(entity);
+                    Ok(entity) => {
+                        // 多帧模型按帧切换显示
+                        if models.len() > 1 {
+                            commands.entity(entity).insert(ModelKeyframe {
+                                frame: frame_index(&shape.attributes),
+                            });
+                        }
+                        result.push(entity);
+                    }
+                    Err(err) => {
+                        despawn_all(commands, &result);
+                        return Err(err);
+                    }
                 }
             }
         }
     }
-    result
+    Ok(result)
+}
+
+fn scene_node_at(scenes_tree: &[SceneNode], index: u32) -> Result<&SceneNode, VoxAnimError> {
+    scenes_tree
+        .get(index as usize)
+        .ok_or(VoxAnimError::BadNodeIndex(index))
+}
+
+// 出错时删掉已经生成的部分
+fn despawn_all(commands: &mut Commands, entities: &[Entity]) {
+    for entity in entities {
+        commands.entity(*entity).despawn_recursive();
+    }
 }
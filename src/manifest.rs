@@ -0,0 +1,363 @@
+// `.voxrig` 角色描述资源
+// 用一个 RON 文件描述角色: 使用哪个 vox 文件, 每个节点怎么生成, 缩放和可替换的部件
+
+use bevy::{
+    asset::{AddAsset, AssetLoader, AssetPath, LoadContext, LoadedAsset},
+    prelude::{
+        App, AssetEvent, Assets, Bundle, Color, Commands, Entity, EventReader, Handle, Plugin,
+        Query, Res, SpatialBundle, Transform, Update, With, Without,
+    },
+    reflect::{TypePath, TypeUuid},
+    utils::{BoxedFuture, HashMap},
+};
+use bevy_vox_mesh::vox_scene_info::VoxSceneInfo;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::{
+    auto_rig::rig_from_vox_node,
+    character::{VoxCharacter, VoxCharacterError, VoxCharacterRig},
+    dealers::{CommonDealers, RigDealers, RigidPartDealers},
+    joint_map::JointMap,
+    layers::VoxLayers,
+    node_match::{match_scene_nodes, FallbackPolicy, MatchedNode, NodeMatcher},
+    palette::{PaletteOverride, VoxPalette},
+    reload::ReloadCharacter,
+    rig::{RigDescription, WeightMode},
+};
+
+/// `.voxrig` 文件的内容, 路径都相对于 `.voxrig` 所在的目录
+///
+/// ```ron
+/// #![enable(unwrap_variant_newtypes)]
+/// (
+///     vox: "boy.vox",
+///     scale: 0.025,
+///     parts: {
+///         "face0": Common,
+///         "body0": RigFile("rigs/body0.rig.ron"),
+///         "body1": AutoRig(Regions),
+///     },
+///     variants: {
+///         "face": ["face0", "face1", "face2", "face3"],
+///     },
+///     fallback: Common,
+///     palette: {
+///         12: (0.8, 0.1, 0.1, 1.0),
+///     },
+/// )
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoxCharacterManifest {
+    pub vox: String,
+    /// 根节点的缩放, vox 中一个体素是一个单位
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    /// 节点名字, 路径或者通配符到生成方式, 见 [`crate::node_match`]
+    pub parts: HashMap<String, PartConfig>,
+    /// 互斥的部件, 每组默认只显示第一个, 见 [`crate::variants::VariantState`]
+    #[serde(default)]
+    pub variants: HashMap<String, Vec<String>>,
+    /// 没有写在 `parts` 中的模型怎么处理
+    #[serde(default)]
+    pub fallback: FallbackPolicy,
+    /// 默认的调色板替换, 序号到 rgba, 见 [`PaletteOverride`]
+    #[serde(default)]
+    pub palette: HashMap<u8, [f32; 4]>,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+/// 一个节点的生成方式
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum PartConfig {
+    /// 不带骨骼, 见 [`CommonDealers`]
+    Common,
+    /// 每个模型刚性绑定到自己的节点, 见 [`RigidPartDealers`]
+    Rigid,
+    /// 直接写在 `.voxrig` 中的骨骼
+    Rig(RigDescription),
+    /// 单独的骨骼描述文件, `.json` 按 JSON 读取, 其他按 RON 读取
+    RigFile(String),
+    /// 从节点下 `joint:xxx` 空节点读取骨骼, 见 [`rig_from_vox_node`]
+    /// key 是路径或者通配符并且匹配到多个节点时, 使用第一个节点下的关节
+    AutoRig(WeightMode),
+}
+
+/// 解析完成的节点配置, 骨骼文件都已经读取
+#[derive(Debug, Clone)]
+pub enum ResolvedPart {
+    Common,
+    Rigid,
+    Rig(RigDealers),
+}
+
+/// 加载完成的 `.voxrig`, 可以直接生成角色
+#[derive(Debug, Clone, TypeUuid, TypePath)]
+#[uuid = "5b0f1f7e-8a8c-4c55-9d6e-3e2a77b0c4d1"]
+pub struct VoxCharacterAsset {
+    /// `xxx.vox#scene`
+    pub scene: Handle<VoxSceneInfo>,
+    pub scale: f32,
+    pub parts: HashMap<String, ResolvedPart>,
+    pub variants: HashMap<String, Vec<String>>,
+    pub fallback: FallbackPolicy,
+    /// vox 文件中的图层名字和颜色
+    pub layers: VoxLayers,
+    pub palette: VoxPalette,
+    /// `.voxrig` 中的调色板替换, 角色上已有的 [`PaletteOverride`] 优先
+    pub palette_override: PaletteOverride,
+}
+
+impl VoxCharacterAsset {
+    /// 每个角色各自的 dealer 配置
+    pub fn rig(&self) -> VoxCharacterRig {
+        let mut rig = VoxCharacterRig::new().with_fallback(self.fallback);
+        for (group, values) in self.variants.iter() {
+            rig = rig.with_variant_group(group.clone(), values.iter().cloned());
+        }
+        for (name, part) in self.parts.iter() {
+            rig = match part {
+                ResolvedPart::Common => rig.with_part(name.clone(), CommonDealers),
+                ResolvedPart::Rigid => rig.with_part(name.clone(), RigidPartDealers),
+                ResolvedPart::Rig(dealer) => rig.with_part(name.clone(), dealer.clone()),
+            };
+        }
+        rig
+    }
+}
+
+#[derive(Default)]
+pub struct VoxCharacterLoader;
+
+impl AssetLoader for VoxCharacterLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let manifest: VoxCharacterManifest = ron::de::from_bytes(bytes)?;
+            let dir = load_context
+                .path()
+                .parent()
+                .map(Path::to_path_buf)
+                .unwrap_or_default();
+            let vox_path = dir.join(&manifest.vox);
+            // 调色板和自动骨骼需要原始的 vox 数据
+            let vox_bytes = load_context.read_asset_bytes(&vox_path).await?;
+            let vox_data = dot_vox::load_bytes(&vox_bytes).map_err(bevy::asset::Error::msg)?;
+
+            // 自动骨骼和生成时一样按 key 匹配节点
+            let keys: Vec<String> = manifest.parts.keys().cloned().collect();
+            let matcher = NodeMatcher::new(keys.iter());
+            let matched = match_scene_nodes(&vox_data.scenes, &matcher);
+
+            let mut parts = HashMap::new();
+            for (name, part) in manifest.parts {
+                let resolved = match part {
+                    PartConfig::Common => ResolvedPart::Common,
+                    PartConfig::Rigid => ResolvedPart::Rigid,
+                    PartConfig::Rig(rig) => ResolvedPart::Rig(rig_dealer(rig, &vox_data)),
+                    PartConfig::RigFile(path) => {
+                        let path = dir.join(path);
+                        let data = load_context.read_asset_bytes(&path).await?;
+                        ResolvedPart::Rig(rig_dealer(parse_rig_file(&path, &data)?, &vox_data))
+                    }
+                    PartConfig::AutoRig(weight_mode) => {
+                        let rig = matched
+                            .iter()
+                            .find_map(|node| match node {
+                                MatchedNode::Part { key, index, .. } if *key == name => {
+                                    Some(*index as u32)
+                                }
+                                _ => None,
+                            })
+                            .and_then(|index| {
+                                rig_from_vox_node(&vox_data.scenes, index, weight_mode)
+                            })
+                            .ok_or_else(|| {
+                                bevy::asset::Error::msg(format!("no joint nodes found in {}", name))
+                            })?;
+                        ResolvedPart::Rig(rig_dealer(rig, &vox_data))
+                    }
+                };
+                parts.insert(name, resolved);
+            }
+
+            let scene_path = AssetPath::new(vox_path.clone(), Some("scene".to_string()));
+            let asset = VoxCharacterAsset {
+                scene: load_context.get_handle(scene_path.clone()),
+                scale: manifest.scale,
+                parts,
+                variants: manifest.variants,
+                fallback: manifest.fallback,
+                layers: VoxLayers::from_vox(&vox_data.layers),
+                palette: VoxPalette::from_vox(&vox_data.palette),
+                palette_override: manifest.palette.iter().fold(
+                    PaletteOverride::new(),
+                    |overrides, (index, [r, g, b, a])| {
+                        overrides.with_color(*index, Color::rgba(*r, *g, *b, *a))
+                    },
+                ),
+            };
+            load_context.set_default_asset(LoadedAsset::new(asset).with_dependency(scene_path));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["voxrig"]
+    }
+}
+
+fn parse_rig_file(path: &Path, data: &[u8]) -> Result<RigDescription, bevy::asset::Error> {
+    let is_json = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    Ok(if is_json {
+        serde_json::from_slice(data)?
+    } else {
+        ron::de::from_bytes(data)?
+    })
+}
+
+fn rig_dealer(rig: RigDescription, vox_data: &dot_vox::DotVoxData) -> RigDealers {
+    RigDealers::new(rig).with_palette(&vox_data.palette)
+}
+
+/// 从 `.voxrig` 生成角色, 资源加载完成后补上 [`crate::character::VoxCharacterBundle`] 的组件
+#[derive(Bundle)]
+pub struct VoxCharacterManifestBundle {
+    pub manifest: Handle<VoxCharacterAsset>,
+    pub joint_map: JointMap,
+    pub spatial: SpatialBundle,
+}
+
+impl VoxCharacterManifestBundle {
+    pub fn new(manifest: Handle<VoxCharacterAsset>) -> Self {
+        Self {
+            manifest,
+            joint_map: JointMap::default(),
+            spatial: SpatialBundle::default(),
+        }
+    }
+}
+
+/// 由 [`crate::character::VoxCharacterPlugin`] 添加
+pub struct VoxCharacterManifestPlugin;
+
+impl Plugin for VoxCharacterManifestPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<VoxCharacterAsset>()
+            .init_asset_loader::<VoxCharacterLoader>()
+            .add_systems(Update, (resolve_manifests, reload_manifests));
+    }
+}
+
+type PendingManifests<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Handle<VoxCharacterAsset>,
+        &'static mut Transform,
+        Option<&'static PaletteOverride>,
+    ),
+    Without<VoxCharacterRig>,
+>;
+
+fn resolve_manifests(
+    mut commands: Commands,
+    mut pending: PendingManifests,
+    manifests: Res<Assets<VoxCharacterAsset>>,
+) {
+    for (character, handle, mut transform, palette_override) in pending.iter_mut() {
+        let Some(manifest) = manifests.get(handle) else {
+            continue;
+        };
+        transform.scale *= manifest.scale;
+        let palette_override = match palette_override {
+            Some(overrides) => manifest.palette_override.merged_into(overrides),
+            None => manifest.palette_override.clone(),
+        };
+        commands.entity(character).insert((
+            manifest.scene.clone(),
+            manifest.rig(),
+            manifest.layers.clone(),
+            manifest.palette.clone(),
+            palette_override,
+        ));
+    }
+}
+
+type ResolvedCharacters<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Handle<VoxCharacterAsset>,
+        Option<&'static VoxLayers>,
+        Option<&'static VoxCharacter>,
+    ),
+    With<VoxCharacterRig>,
+>;
+
+// `.voxrig` 或者它的 vox 文件修改之后, 用新的配置替换角色上的组件并重新生成
+// 缩放和角色上的调色板替换保持不变, 图层保留运行时的显示状态
+fn reload_manifests(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<VoxCharacterAsset>>,
+    characters: ResolvedCharacters,
+    manifests: Res<Assets<VoxCharacterAsset>>,
+) {
+    for event in events.iter() {
+        let AssetEvent::Modified { handle } = event else {
+            continue;
+        };
+        let Some(manifest) = manifests.get(handle) else {
+            continue;
+        };
+        for (character, _, old_layers, spawned) in characters
+            .iter()
+            .filter(|(_, character_manifest, _, _)| *character_manifest == handle)
+        {
+            let mut layers = manifest.layers.clone();
+            if let Some(old_layers) = old_layers {
+                layers.keep_hidden(old_layers);
+            }
+            let mut character_commands = commands.entity(character);
+            character_commands.insert((
+                manifest.scene.clone(),
+                manifest.rig(),
+                manifest.palette.clone(),
+                layers,
+            ));
+            if spawned.is_some() {
+                character_commands.insert(ReloadCharacter);
+            } else {
+                character_commands.remove::<VoxCharacterError>();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rig_file_format_follows_extension() {
+        let ron_data = include_bytes!("../assets/rigs/body0.rig.ron");
+        let rig = parse_rig_file(Path::new("rigs/body0.rig.ron"), ron_data).unwrap();
+        let json_data = serde_json::to_vec(&rig).unwrap();
+        let from_json = parse_rig_file(Path::new("rigs/body0.rig.JSON"), &json_data).unwrap();
+        let names = |rig: &RigDescription| -> Vec<String> {
+            rig.joints.iter().map(|joint| joint.name.clone()).collect()
+        };
+        assert_eq!(names(&from_json), names(&rig));
+        assert!(parse_rig_file(Path::new("rigs/body0.rig.ron"), &json_data).is_err());
+    }
+}
@@ -1 +1,48 @@
-//
\ No newline at end of file
+// 从 vox mesh 生成 skinned mesh 的工具
+// 复制原 mesh 的所有属性 (任意格式, 包括 tangent 和自定义属性), dealer 只需要给出关节和权重
+
+use bevy::{
+    prelude::{Mesh, Vec3},
+    render::mesh::VertexAttributeValues,
+};
+
+/// 复制 `mesh` 并加上蒙皮属性
+/// `joint_indices` 和 `joint_weights` 的长度需要和顶点数量一致
+pub fn skinned_mesh_from(
+    mesh: &Mesh,
+    joint_indices: Vec<[u16; 4]>,
+    joint_weights: Vec<[f32; 4]>,
+) -> Mesh {
+    let mut skinned = mesh.clone();
+    skinned.insert_attribute(
+        Mesh::ATTRIBUTE_JOINT_INDEX,
+        VertexAttributeValues::Uint16x4(joint_indices),
+    );
+    skinned.insert_attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT, joint_weights);
+    skinned
+}
+
+/// 每个顶点的位置, 没有位置属性时返回空
+pub fn vertex_positions(mesh: &Mesh) -> Vec<Vec3> {
+    match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+        Some(VertexAttributeValues::Float32x3(positions)) => {
+            positions.iter().map(|p| Vec3::from(*p)).collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// 每个顶点的颜色 (线性 rgba), 没有颜色属性时返回空
+pub fn vertex_colors(mesh: &Mesh) -> Vec<[f32; 4]> {
+    match mesh.attribute(Mesh::ATTRIBUTE_COLOR) {
+        Some(VertexAttributeValues::Float32x4(colors)) => colors.clone(),
+        Some(VertexAttributeValues::Float32x3(colors)) => {
+            colors.iter().map(|[r, g, b]| [*r, *g, *b, 1.0]).collect()
+        }
+        Some(VertexAttributeValues::Unorm8x4(colors)) => colors
+            .iter()
+            .map(|c| c.map(|channel| channel as f32 / 255.0))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,208 @@
+// 配置中的节点名字匹配
+// `body0` 只比较节点名字, `character/body/arm` 比较从根节点开始的路径, 两种都可以用 `face*` 这样的通配符
+
+use bevy::{log::warn, prelude::Transform};
+use dot_vox::SceneNode;
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+
+use crate::{auto_rig::is_joint_node, vox_transform::node_transform};
+
+/// 路径的分隔符
+pub const PATH_SEPARATOR: char = '/';
+
+/// 从配置的 key 生成的匹配规则
+/// 没有通配符的 key 优先, 之后按 key 的字母顺序, 第一个匹配的生效
+pub struct NodeMatcher {
+    patterns: Vec<(String, Pattern)>,
+}
+
+impl NodeMatcher {
+    pub fn new<'a>(keys: impl Iterator<Item = &'a String>) -> Self {
+        let mut patterns: Vec<(String, Pattern)> = keys
+            .map(|key| {
+                let pattern = Pattern::new(key).unwrap_or_else(|err| {
+                    warn!("bad node pattern {}: {}, matched literally", key, err);
+                    Pattern::new(&Pattern::escape(key)).expect("escaped pattern")
+                });
+                (key.clone(), pattern)
+            })
+            .collect();
+        patterns
+            .sort_by(|(a, _), (b, _)| is_wildcard(a).cmp(&is_wildcard(b)).then_with(|| a.cmp(b)));
+        Self { patterns }
+    }
+
+    /// 返回匹配到的 key, `path` 包含节点自己的名字
+    pub fn find(&self, name: &str, path: &str) -> Option<&str> {
+        self.patterns
+            .iter()
+            .find(|(key, pattern)| {
+                if key.contains(PATH_SEPARATOR) {
+                    pattern.matches(path)
+                } else {
+                    pattern.matches(name)
+                }
+            })
+            .map(|(key, _)| key.as_str())
+    }
+}
+
+fn is_wildcard(key: &str) -> bool {
+    key.contains(['*', '?', '['])
+}
+
+/// 配置中没有的模型怎么处理
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FallbackPolicy {
+    /// 不生成
+    #[default]
+    Skip,
+    /// 使用 [`crate::dealers::CommonDealers`] 生成, 道具和饰品不需要一个个配置
+    Common,
+    /// 返回 [`crate::error::VoxAnimError::UnmatchedNode`]
+    Error,
+}
+
+/// 场景中需要生成的节点
+/// `parent` 是所有祖先 transform 节点静止姿态的乘积, 生成时乘到节点自己的 Transform 上
+#[derive(Debug, Clone, PartialEq)]
+pub enum MatchedNode<'a> {
+    /// 匹配到配置中的 `key`
+    Part {
+        key: &'a str,
+        index: usize,
+        parent: Transform,
+    },
+    /// 没有被任何配置覆盖的模型, 记录直接包住它的 transform 节点
+    /// `name` 是节点的路径, 没有名字的节点使用 `#序号`
+    Unmatched {
+        name: String,
+        index: usize,
+        parent: Transform,
+    },
+}
+
+/// 从根节点开始找到所有匹配的 transform 节点
+/// 匹配到的节点整个交给对应的 dealer, 不再继续匹配它的子节点
+/// `joint:xxx` 关节节点和它的子节点都跳过
+pub fn match_scene_nodes<'a>(
+    scenes: &[SceneNode],
+    matcher: &'a NodeMatcher,
+) -> Vec<MatchedNode<'a>> {
+    let mut result = Vec::new();
+    let mut path = Vec::new();
+    collect_matches(
+        scenes,
+        0,
+        Transform::IDENTITY,
+        &mut path,
+        matcher,
+        &mut result,
+    );
+    result
+}
+
+fn collect_matches<'a>(
+    scenes: &[SceneNode],
+    index: u32,
+    parent: Transform,
+    path: &mut Vec<String>,
+    matcher: &'a NodeMatcher,
+    result: &mut Vec<MatchedNode<'a>>,
+) {
+    let Some(node) = scenes.get(index as usize) else {
+        warn!("scene node {} does not exist", index);
+        return;
+    };
+    match node {
+        SceneNode::Transform {
+            attributes,
+            child,
+            frames,
+            ..
+        } => {
+            let name = attributes.get("_name");
+            if name.is_some_and(|name| is_joint_node(name)) {
+                return;
+            }
+            if let Some(name) = name {
+                path.push(name.clone());
+                let full_path = path.join(&PATH_SEPARATOR.to_string());
+                if let Some(key) = matcher.find(name, &full_path) {
+                    result.push(MatchedNode::Part {
+                        key,
+                        index: index as usize,
+                        parent,
+                    });
+                    path.pop();
+                    return;
+                }
+            }
+            if let Some(SceneNode::Shape { .. }) = scenes.get(*child as usize) {
+                let name = match name {
+                    Some(_) => path.join(&PATH_SEPARATOR.to_string()),
+                    None => format!("#{}", index),
+                };
+                result.push(MatchedNode::Unmatched {
+                    name,
+                    index: index as usize,
+                    parent,
+                });
+            } else {
+                let parent = parent * node_transform(frames);
+                collect_matches(scenes, *child, parent, path, matcher, result);
+            }
+            if name.is_some() {
+                path.pop();
+            }
+        }
+        SceneNode::Group { children, .. } => {
+            for child in children {
+                collect_matches(scenes, *child, parent, path, matcher, result);
+            }
+        }
+        SceneNode::Shape { .. } => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(keys: &[&str]) -> NodeMatcher {
+        let keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+        NodeMatcher::new(keys.iter())
+    }
+
+    #[test]
+    fn literal_keys_before_wildcards() {
+        let matcher = matcher(&["face*", "face0"]);
+        assert_eq!(matcher.find("face0", "boy/face0"), Some("face0"));
+        assert_eq!(matcher.find("face1", "boy/face1"), Some("face*"));
+        assert_eq!(matcher.find("body", "boy/body"), None);
+    }
+
+    #[test]
+    fn wildcards_in_alphabetical_order() {
+        let matcher = matcher(&["f*", "fa*"]);
+        assert_eq!(matcher.find("face", "face"), Some("f*"));
+    }
+
+    #[test]
+    fn path_keys_match_full_path() {
+        let name_first = matcher(&["boy/*/arm", "arm"]);
+        assert_eq!(name_first.find("arm", "boy/body/arm"), Some("arm"));
+
+        let path_only = matcher(&["boy/*/arm"]);
+        assert_eq!(path_only.find("arm", "boy/body/arm"), Some("boy/*/arm"));
+        assert_eq!(path_only.find("arm", "girl/body/arm"), None);
+    }
+
+    #[test]
+    fn bad_pattern_matches_literally() {
+        let bad = matcher(&["arm["]);
+        assert_eq!(bad.find("arm[", "arm["), Some("arm["));
+        assert_eq!(bad.find("arm", "arm"), None);
+    }
+}
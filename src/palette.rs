@@ -0,0 +1,264 @@
+// 每个角色自己的调色板替换
+// 队伍颜色, 衣服颜色这类变化不需要复制 vox 文件, 用同样替换的角色之间共用 mesh
+
+use bevy::{
+    asset::HandleId,
+    ecs::system::SystemParam,
+    log::warn,
+    prelude::{
+        App, Assets, Changed, Color, Commands, Component, Entity, Handle, Mesh, Or, Plugin, Query,
+        RemovedComponents, ResMut, Resource, Update, With,
+    },
+    utils::HashMap,
+};
+
+use crate::{
+    character::VoxCharacter,
+    mesh_helper::vertex_colors,
+    sockets::CharacterHierarchy,
+    weights::{palette_index, vox_palette_colors},
+};
+
+/// vox 文件的调色板, 用于从顶点色找回调色板序号
+/// 通过 `.voxrig` 生成的角色会自动加上
+#[derive(Debug, Clone, Default, Component)]
+pub struct VoxPalette {
+    colors: Vec<[f32; 4]>,
+}
+
+impl VoxPalette {
+    pub fn from_vox(palette: &[dot_vox::Color]) -> Self {
+        Self {
+            colors: vox_palette_colors(palette),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+}
+
+/// 放在角色根节点上, 调色板序号到新的颜色
+/// 修改之后下一帧更新角色下所有的模型, 清空后恢复原来的颜色
+#[derive(Debug, Clone, Default, PartialEq, Component)]
+pub struct PaletteOverride {
+    colors: HashMap<u8, Color>,
+}
+
+impl PaletteOverride {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_color(mut self, index: u8, color: Color) -> Self {
+        self.set(index, color);
+        self
+    }
+
+    pub fn set(&mut self, index: u8, color: Color) {
+        self.colors.insert(index, color);
+    }
+
+    pub fn remove(&mut self, index: u8) {
+        self.colors.remove(&index);
+    }
+
+    pub fn clear(&mut self) {
+        self.colors.clear();
+    }
+
+    pub fn get(&self, index: u8) -> Option<Color> {
+        self.colors.get(&index).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u8, Color)> + '_ {
+        self.colors.iter().map(|(index, color)| (*index, *color))
+    }
+
+    /// `other` 中已有的序号不覆盖
+    pub fn merged_into(&self, other: &PaletteOverride) -> PaletteOverride {
+        let mut merged = other.clone();
+        for (index, color) in self.iter() {
+            merged.colors.entry(index).or_insert(color);
+        }
+        merged
+    }
+
+    // 缓存的 key, 和顺序无关
+    fn key(&self) -> OverrideKey {
+        let mut key: OverrideKey = self
+            .iter()
+            .map(|(index, color)| (index, color.as_linear_rgba_f32().map(f32::to_bits)))
+            .collect();
+        key.sort_by_key(|(index, _)| *index);
+        key
+    }
+}
+
+type OverrideKey = Vec<(u8, [u32; 4])>;
+
+/// 替换颜色前的 mesh, 用于再次替换或者恢复
+#[derive(Debug, Clone, Component)]
+pub struct PaletteSource(pub Handle<Mesh>);
+
+/// 换过颜色的 mesh, 按源 mesh 和替换内容缓存
+/// 只保存弱引用, 没有角色再使用的 mesh 会被释放, 对应的记录在下次更新时删除
+#[derive(Debug, Default, Resource)]
+pub struct PaletteCache {
+    entries: HashMap<(HandleId, OverrideKey), Handle<Mesh>>,
+}
+
+impl PaletteCache {
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn get(
+        &self,
+        key: &(HandleId, OverrideKey),
+        mesh_assets: &Assets<Mesh>,
+    ) -> Option<Handle<Mesh>> {
+        let weak = self.entries.get(key)?;
+        mesh_assets
+            .contains(weak)
+            .then(|| mesh_assets.get_handle(weak))
+    }
+
+    fn insert(&mut self, key: (HandleId, OverrideKey), handle: &Handle<Mesh>) {
+        self.entries.insert(key, handle.clone_weak());
+    }
+
+    // 删除已经被释放的 mesh
+    fn remove_unused(&mut self, mesh_assets: &Assets<Mesh>) {
+        self.entries.retain(|_, weak| mesh_assets.contains(weak));
+    }
+}
+
+/// 复制 `mesh` 并替换顶点色, 没有顶点色或者没有任何顶点被替换时返回 `None`
+/// 顶点色按完全相同的调色板颜色找回序号, 调色板中有相同颜色时按第一个序号替换
+pub fn recolor_mesh(
+    mesh: &Mesh,
+    palette: &VoxPalette,
+    overrides: &PaletteOverride,
+) -> Option<Mesh> {
+    let mut colors = vertex_colors(mesh);
+    let mut changed = false;
+    for color in colors.iter_mut() {
+        let Some(index) = palette_index(*color, &palette.colors) else {
+            continue;
+        };
+        if let Some(target) = overrides.get(index) {
+            *color = target.as_linear_rgba_f32();
+            changed = true;
+        }
+    }
+    if !changed {
+        return None;
+    }
+    let mut recolored = mesh.clone();
+    recolored.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    Some(recolored)
+}
+
+/// 由 [`crate::character::VoxCharacterPlugin`] 添加
+pub struct VoxPalettePlugin;
+
+impl Plugin for VoxPalettePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PaletteCache>()
+            .add_systems(Update, apply_palette_overrides);
+    }
+}
+
+type ChangedCharacters<'w, 's> = Query<
+    'w,
+    's,
+    Entity,
+    (
+        With<VoxCharacter>,
+        Or<(Changed<PaletteOverride>, Changed<VoxCharacter>)>,
+    ),
+>;
+
+/// 角色下的节点
+#[derive(SystemParam)]
+struct CharacterNodes<'w, 's> {
+    hierarchy: CharacterHierarchy<'w, 's>,
+    meshes: Query<'w, 's, (&'static mut Handle<Mesh>, Option<&'static PaletteSource>)>,
+}
+
+fn apply_palette_overrides(
+    mut commands: Commands,
+    characters: Query<(Option<&PaletteOverride>, Option<&VoxPalette>), With<VoxCharacter>>,
+    changed: ChangedCharacters,
+    mut removed: RemovedComponents<PaletteOverride>,
+    mut nodes: CharacterNodes,
+    mut mesh_assets: ResMut<Assets<Mesh>>,
+    mut cache: ResMut<PaletteCache>,
+) {
+    let mut dirty: Vec<Entity> = changed.iter().chain(removed.iter()).collect();
+    if dirty.is_empty() {
+        return;
+    }
+    dirty.sort();
+    dirty.dedup();
+    cache.remove_unused(&mesh_assets);
+
+    let empty = PaletteOverride::default();
+    for character in dirty {
+        let Ok((overrides, palette)) = characters.get(character) else {
+            continue;
+        };
+        let overrides = overrides.unwrap_or(&empty);
+        let palette = match palette {
+            Some(palette) => palette,
+            None if overrides.is_empty() => &VoxPalette::default(),
+            None => {
+                warn!("{:?} has PaletteOverride but no VoxPalette", character);
+                continue;
+            }
+        };
+        let key = overrides.key();
+
+        // 挂载的物体不属于角色, 它自己和它的子节点都不替换
+        for node in nodes.hierarchy.descendants(character) {
+            let Ok((mut handle, source)) = nodes.meshes.get_mut(node) else {
+                continue;
+            };
+            let source = source.map_or_else(|| handle.clone(), |source| source.0.clone());
+            let target = if overrides.is_empty() {
+                source.clone()
+            } else {
+                let cache_key = (source.id(), key.clone());
+                match cache.get(&cache_key, &mesh_assets) {
+                    Some(cached) => cached,
+                    None => {
+                        // 没有用到被替换的颜色时直接用源 mesh
+                        let target = mesh_assets
+                            .get(&source)
+                            .and_then(|mesh| recolor_mesh(mesh, palette, overrides))
+                            .map_or_else(|| source.clone(), |mesh| mesh_assets.add(mesh));
+                        cache.insert(cache_key, &target);
+                        target
+                    }
+                }
+            };
+            if *handle != target {
+                *handle = target;
+                commands.entity(node).insert(PaletteSource(source));
+            }
+        }
+    }
+}
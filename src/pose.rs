@@ -1,52 +1,23 @@
 // 造型 数据
 
 use bevy::{
-    prelude::{
-        Children, Component, Entity, Plugin, Quat, Query, Res, ResMut, Resource, Transform, Update,
-        Vec3,
-    },
+    prelude::{Entity, Name, Plugin, Quat, Query, ResMut, Resource, Transform, Update, Vec3},
     utils::HashMap,
 };
 use bevy_egui::{EguiContexts, EguiPlugin};
 
-use crate::types::{LeftArm, LeftHand, RightArm, RightHand, RightLeg, LeftLeg};
+use crate::joint_map::{JointMap, JointMapPlugin};
 
-#[derive(Debug, Resource)]
-pub struct BoyEntity {
-    pub boy_entity: Option<Entity>,
+/// 姿态编辑器当前编辑的角色, 没有选中时使用第一个带 [`JointMap`] 的角色
+#[derive(Debug, Default, Resource)]
+pub struct PoseEditTarget {
+    pub character: Option<Entity>,
 }
 
+/// 关节名字 -> (旋转, 位置)
 #[derive(Debug, Clone, Default)]
 pub struct Pose {
-    right_hand: (Quat, Vec3),
-    left_hand: (Quat, Vec3),
-    right_arm: (Quat, Vec3),
-    left_arm: (Quat, Vec3),
-    right_leg: (Quat, Vec3),
-    left_leg: (Quat, Vec3),
-}
-
-// 找到子类中的某个数据
-pub fn find_entity<T>(
-    root: Entity,
-    children_query: &Query<&Children>,
-    t_query: &Query<&T>,
-) -> Result<Entity, ()>
-where
-    T: Component,
-{
-    if let Ok(children) = children_query.get(root) {
-        for child in children.iter() {
-            if let Ok(_) = t_query.get(*child) {
-                return Ok(*child);
-            }
-            if let Ok(entity) = find_entity::<T>(*child, children_query, t_query) {
-                return Ok(entity);
-            }
-        }
-    }
-
-    Err(())
+    pub joints: HashMap<String, (Quat, Vec3)>,
 }
 
 // 加载某个数据
@@ -63,67 +34,60 @@ impl Plugin for PoseEditPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
         app.insert_resource(PoseMap {
             map_data: HashMap::new(),
-        });
+        })
+        .init_resource::<PoseEditTarget>();
         if !app.is_plugin_added::<EguiPlugin>() {
             app.add_plugins(EguiPlugin);
         }
+        if !app.is_plugin_added::<JointMapPlugin>() {
+            app.add_plugins(JointMapPlugin);
+        }
         app.add_systems(Update, pose_edit_ui);
     }
 }
 
 // 操作造型的ui
 fn pose_edit_ui(
-    boy_entity: Res<BoyEntity>,
+    mut target: ResMut<PoseEditTarget>,
     mut contexts: EguiContexts,
     mut pose_map: ResMut<PoseMap>,
-    children_query: Query<&Children>,
-    right_arm_query: Query<&RightArm>,
-    left_arm_query: Query<&LeftArm>,
-    right_leg_query: Query<&RightLeg>,
-    left_leg_query: Query<&LeftLeg>,
-    right_hand_query: Query<&RightHand>,
-    left_hand_query: Query<&LeftHand>,
+    joint_maps: Query<(Entity, &JointMap, Option<&Name>)>,
     mut transforms_query: Query<&mut Transform>,
 ) {
+    // 选中的角色被删除之后换成第一个
+    if target.character.is_none_or(|e| !joint_maps.contains(e)) {
+        target.character = joint_maps.iter().next().map(|(entity, _, _)| entity);
+    }
     let ctx = contexts.ctx_mut();
-    if let Some(root) = boy_entity.boy_entity {
+    if let Some((_, joint_map, _)) = target.character.and_then(|root| joint_maps.get(root).ok()) {
         bevy_egui::egui::Window::new("Pose Edit")
             .resizable(true)
             .default_height(20.0)
             .default_width(5.0)
             .show(ctx, |ui| {
+                // 选择要编辑的角色
+                bevy_egui::egui::ComboBox::from_label("Character")
+                    .selected_text(character_label(target.character, &joint_maps))
+                    .show_ui(ui, |ui| {
+                        for (entity, _, _) in joint_maps.iter() {
+                            ui.selectable_value(
+                                &mut target.character,
+                                Some(entity),
+                                character_label(Some(entity), &joint_maps),
+                            );
+                        }
+                    });
                 // 这里的 循环的展示一下数据 姿势列表
                 bevy_egui::egui::ScrollArea::both().show(ui, |ui| {
                     for (name, pose) in &pose_map.map_data {
                         if ui.button(name).clicked() {
-                            load_pose_entity(
-                                root,
-                                pose.clone(),
-                                &children_query,
-                                &right_arm_query,
-                                &left_arm_query,
-                                &right_leg_query,
-                                &left_leg_query,
-                                &right_hand_query,
-                                &left_hand_query,
-                                &mut transforms_query,
-                            );
+                            load_pose_entity(joint_map, pose, &mut transforms_query);
                         }
                     }
                 });
                 // 这里有 记录当前的 pose 和 加载当前的pose
                 if ui.button("Save Pose").clicked() {
-                    let pose = get_pose_entity(
-                        root,
-                        &children_query,
-                        &right_arm_query,
-                        &left_arm_query,
-                        &right_leg_query,
-                        &left_leg_query,
-                        &right_hand_query,
-                        &left_hand_query,
-                        &transforms_query,
-                    );
+                    let pose = get_pose_entity(joint_map, &transforms_query);
                     // 这里暂时是默认值
                     let index = pose_map.map_data.len();
                     pose_map.map_data.insert(format!("pose{}", index), pose);
@@ -132,127 +96,40 @@ fn pose_edit_ui(
     }
 }
 
-// 加载数据
-fn load_pose_entity(
-    root: Entity,
-    pose: Pose,
-    children_query: &Query<&Children>,
-    right_arm_query: &Query<&RightArm>,
-    left_arm_query: &Query<&LeftArm>,
-    right_leg_query: &Query<&RightLeg>,
-    left_leg_query: &Query<&LeftLeg>,
-    right_hand_query: &Query<&RightHand>,
-    left_hand_query: &Query<&LeftHand>,
-    transforms_query: &mut Query<&mut Transform>,
-) {
-    load_for_entity(
-        root,
-        children_query,
-        right_arm_query,
-        transforms_query,
-        pose.right_arm,
-    );
-    load_for_entity(
-        root,
-        children_query,
-        left_arm_query,
-        transforms_query,
-        pose.left_arm,
-    );
-    load_for_entity(
-        root,
-        children_query,
-        right_leg_query,
-        transforms_query,
-        pose.right_leg,
-    );
-    load_for_entity(
-        root,
-        children_query,
-        left_leg_query,
-        transforms_query,
-        pose.left_leg,
-    );
-    load_for_entity(
-        root,
-        children_query,
-        right_hand_query,
-        transforms_query,
-        pose.right_hand,
-    );
-    load_for_entity(
-        root,
-        children_query,
-        left_hand_query,
-        transforms_query,
-        pose.left_hand,
-    );
+fn character_label(
+    character: Option<Entity>,
+    joint_maps: &Query<(Entity, &JointMap, Option<&Name>)>,
+) -> String {
+    match character.and_then(|entity| joint_maps.get(entity).ok()) {
+        Some((_, _, Some(name))) => name.to_string(),
+        Some((entity, _, None)) => format!("{:?}", entity),
+        None => String::from("-"),
+    }
 }
 
-fn load_for_entity<T>(
-    root: Entity,
-    children_query: &Query<&Children>,
-    t_query: &Query<&T>,
+// 加载数据
+pub fn load_pose_entity(
+    joint_map: &JointMap,
+    pose: &Pose,
     transforms_query: &mut Query<&mut Transform>,
-    data: (Quat, Vec3),
-) where
-    T: Component,
-{
-    if let Ok(entity) = find_entity(root, children_query, t_query) {
-        if let Ok(mut tfr) = transforms_query.get_mut(entity) {
-            tfr.rotation = data.0;
-            tfr.translation = data.1;
+) {
+    for (name, data) in pose.joints.iter() {
+        if let Some(entity) = joint_map.get(name) {
+            if let Ok(mut tfr) = transforms_query.get_mut(entity) {
+                tfr.rotation = data.0;
+                tfr.translation = data.1;
+            }
         }
     }
 }
 
-fn get_pose_entity(
-    root: Entity,
-    children_query: &Query<&Children>,
-    right_arm_query: &Query<&RightArm>,
-    left_arm_query: &Query<&LeftArm>,
-    right_leg_query: &Query<&RightLeg>,
-    left_leg_query: &Query<&LeftLeg>,
-    right_hand_query: &Query<&RightHand>,
-    left_hand_query: &Query<&LeftHand>,
-    transforms_query: &Query<&mut Transform>,
-) -> Pose {
+pub fn get_pose_entity(joint_map: &JointMap, transforms_query: &Query<&mut Transform>) -> Pose {
     let mut pose = Pose::default();
-    if let Some(data) = get_for_entity(root, children_query, right_arm_query, transforms_query) {
-        pose.right_arm = data;
-    }
-    if let Some(data) = get_for_entity(root, children_query, left_arm_query, transforms_query) {
-        pose.left_arm = data;
-    }
-    if let Some(data) = get_for_entity(root, children_query, right_leg_query, transforms_query) {
-        pose.right_leg = data;
-    }
-    if let Some(data) = get_for_entity(root, children_query, left_leg_query, transforms_query) {
-        pose.left_leg = data;
-    }
-    if let Some(data) = get_for_entity(root, children_query, right_hand_query, transforms_query) {
-        pose.right_hand = data;
-    }
-    if let Some(data) = get_for_entity(root, children_query, left_hand_query, transforms_query) {
-        pose.left_hand = data;
-    }
-    pose
-}
-
-// 获取单个值
-fn get_for_entity<T>(
-    root: Entity,
-    children_query: &Query<&Children>,
-    t_query: &Query<&T>,
-    transforms_query: &Query<&mut Transform>,
-) -> Option<(Quat, Vec3)>
-where
-    T: Component,
-{
-    if let Ok(entity) = find_entity(root, children_query, t_query) {
-        if let Ok(tfr) = transforms_query.get(entity) {
-            return Some((tfr.rotation, tfr.translation));
+    for (name, entity) in joint_map.iter() {
+        if let Ok(tfr) = transforms_query.get(*entity) {
+            pose.joints
+                .insert(name.clone(), (tfr.rotation, tfr.translation));
         }
     }
-    None
+    pose
 }
@@ -0,0 +1,247 @@
+// 热重载
+// vox 文件保存之后在原来的根节点下重新生成角色
+// 根节点上的组件 (动画播放, 部件, 图层, 调色板) 都保留, 关节的姿态和挂载的物体在新的层级上恢复
+
+use bevy::{
+    asset::HandleId,
+    log::error,
+    prelude::{
+        apply_deferred, App, AssetEvent, AssetServer, BuildChildren, Commands, Component,
+        DespawnRecursiveExt, Entity, EventReader, EventWriter, Handle, IntoSystemConfigs, Mesh,
+        Plugin, Query, Res, ResMut, Transform, Update, With,
+    },
+    utils::HashMap,
+};
+use bevy_vox_mesh::vox_scene_info::VoxSceneInfo;
+use std::path::PathBuf;
+
+use crate::{
+    character::{
+        sorted_children, CharacterReady, CharacterSpawner, VoxCharacter, VoxCharacterError,
+        VoxCharacterRig,
+    },
+    joint_map::{sync_joint_maps, JointMap},
+    manifest::VoxCharacterAsset,
+    palette::PaletteCache,
+    sockets::{attach_items, AttachToSocket, Attached},
+};
+
+/// 插入之后, 等场景和模型加载完成时重新生成这个角色
+/// vox 文件修改时会自动插入, 新的层级生成失败时保留原来的
+#[derive(Debug, Clone, Copy, Default, Component)]
+pub struct ReloadCharacter;
+
+// 重新生成之前每个关节的 Transform, 按名字恢复到新的关节上
+#[derive(Debug, Clone, Component)]
+struct PendingPose(HashMap<String, Transform>);
+
+/// 由 [`crate::character::VoxCharacterPlugin`] 添加
+pub struct VoxReloadPlugin;
+
+impl Plugin for VoxReloadPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                // 新的层级生成之后 attach_items 在同一帧挂回去, 物体不会有一帧停在世界坐标里
+                (mark_modified_characters, reload_characters, apply_deferred)
+                    .chain()
+                    .before(attach_items),
+                restore_pose.after(sync_joint_maps),
+            ),
+        );
+    }
+}
+
+type LoadedCharacters<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Handle<VoxSceneInfo>,
+        &'static VoxCharacterRig,
+        Option<&'static Handle<VoxCharacterAsset>>,
+    ),
+    With<VoxCharacter>,
+>;
+
+type FailedCharacters<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Handle<VoxSceneInfo>,
+        Option<&'static Handle<VoxCharacterAsset>>,
+    ),
+    With<VoxCharacterError>,
+>;
+
+// 场景或者它的模型修改之后标记使用它的角色, 同时清掉缓存
+// `.voxrig` 的骨骼, 调色板和图层也是从 vox 文件读取的, 这些角色重新加载 `.voxrig`, 见 [`crate::manifest`]
+fn mark_modified_characters(
+    mut commands: Commands,
+    mut scene_events: EventReader<AssetEvent<VoxSceneInfo>>,
+    mut mesh_events: EventReader<AssetEvent<Mesh>>,
+    characters: LoadedCharacters,
+    failed: FailedCharacters,
+    asset_server: Res<AssetServer>,
+    mut palette_cache: ResMut<PaletteCache>,
+) {
+    let scenes: Vec<HandleId> = scene_events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Modified { handle } => Some(handle.id()),
+            _ => None,
+        })
+        .collect();
+    // 代码生成的 mesh 没有路径, 不会触发
+    let paths: Vec<PathBuf> = mesh_events
+        .iter()
+        .filter_map(|event| match event {
+            AssetEvent::Modified { handle } => asset_server.get_handle_path(handle),
+            _ => None,
+        })
+        .map(|path| path.path().to_path_buf())
+        .collect();
+    if scenes.is_empty() && paths.is_empty() {
+        return;
+    }
+
+    let is_modified = |scene: &Handle<VoxSceneInfo>| {
+        scenes.contains(&scene.id())
+            || asset_server
+                .get_handle_path(scene)
+                .is_some_and(|path| paths.iter().any(|p| p == path.path()))
+    };
+
+    let mut manifests: Vec<HandleId> = Vec::new();
+    let mut modified = false;
+    for (character, scene, rig, manifest) in characters.iter() {
+        if !is_modified(scene) {
+            continue;
+        }
+        modified = true;
+        if let Some(manifest) = manifest {
+            manifests.push(manifest.id());
+            continue;
+        }
+        for dealer in rig.parts.values() {
+            dealer.clear_cache();
+        }
+        commands.entity(character).insert(ReloadCharacter);
+    }
+    if modified {
+        palette_cache.clear();
+    }
+    // 生成失败的角色重新尝试
+    for (character, scene, manifest) in failed.iter() {
+        if !is_modified(scene) {
+            continue;
+        }
+        match manifest {
+            Some(manifest) => manifests.push(manifest.id()),
+            None => {
+                commands.entity(character).remove::<VoxCharacterError>();
+            }
+        }
+    }
+
+    manifests.sort();
+    manifests.dedup();
+    for manifest in manifests {
+        if let Some(path) = asset_server.get_handle_path(manifest) {
+            asset_server.reload_asset(path);
+        }
+    }
+}
+
+type ReloadingCharacters<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Handle<VoxSceneInfo>,
+        &'static VoxCharacterRig,
+        &'static VoxCharacter,
+        Option<&'static JointMap>,
+    ),
+    With<ReloadCharacter>,
+>;
+
+fn reload_characters(
+    mut commands: Commands,
+    characters: ReloadingCharacters,
+    transforms: Query<&Transform>,
+    attached: Query<(Entity, &Attached)>,
+    mut spawner: CharacterSpawner,
+    mut attach: EventWriter<AttachToSocket>,
+    mut ready: EventWriter<CharacterReady>,
+) {
+    for (character, scene, rig, old, joint_map) in characters.iter() {
+        let Some(result) = spawner.spawn(&mut commands, scene, rig) else {
+            continue;
+        };
+        commands.entity(character).remove::<ReloadCharacter>();
+        let parts = match result {
+            Ok(parts) => parts,
+            Err(err) => {
+                error!("failed to reload character {:?}: {}", character, err);
+                continue;
+            }
+        };
+
+        if let Some(joint_map) = joint_map {
+            let pose = joint_map
+                .iter()
+                .filter_map(|(name, joint)| Some((name.clone(), *transforms.get(*joint).ok()?)))
+                .collect();
+            commands.entity(character).insert(PendingPose(pose));
+        }
+        // 挂载的物体先取下, 保持相对挂载点的位置, 在新的挂载点上挂回去
+        for (item, attached) in attached.iter() {
+            if attached.character == character {
+                commands.entity(item).remove_parent();
+                attach.send(AttachToSocket {
+                    character,
+                    socket: attached.socket.clone(),
+                    item,
+                });
+            }
+        }
+        for entity in old.parts.values().flatten() {
+            commands.entity(*entity).despawn_recursive();
+        }
+
+        let children = sorted_children(&parts);
+        commands
+            .entity(character)
+            .push_children(&children)
+            .insert(VoxCharacter { parts });
+        ready.send(CharacterReady(character));
+    }
+}
+
+fn restore_pose(
+    mut commands: Commands,
+    characters: Query<(Entity, &PendingPose, &JointMap)>,
+    mut transforms: Query<&mut Transform>,
+) {
+    for (character, pose, joint_map) in characters.iter() {
+        // 关节表还指向旧的关节, 等它重建
+        if joint_map
+            .iter()
+            .any(|(_, joint)| !transforms.contains(*joint))
+        {
+            continue;
+        }
+        for (name, saved) in pose.0.iter() {
+            let Some(joint) = joint_map.get(name) else {
+                continue;
+            };
+            if let Ok(mut transform) = transforms.get_mut(joint) {
+                *transform = *saved;
+            }
+        }
+        commands.entity(character).remove::<PendingPose>();
+    }
+}
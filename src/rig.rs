@@ -0,0 +1,324 @@
+// 骨骼描述文件 (RON / JSON)
+// 用数据代替为每一个角色手写 Dealer
+
+use bevy::{
+    ecs::system::EntityCommands,
+    log::warn,
+    prelude::{Color, EulerRot, Mat4, Quat, Transform, Vec3},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::error::VoxAnimError;
+use crate::skeleton::SkeletonBuilder;
+use crate::types::{Body, LeftArm, LeftHand, LeftLeg, RightArm, RightHand, RightLeg};
+use crate::weights::{single_joint, DistanceWeights, PaletteWeights, VertexWeights};
+
+/// 一个 skinned mesh 的完整骨骼描述
+///
+/// ```ron
+/// #![enable(implicit_some)]
+/// (
+///     joints: [
+///         (name: "right_arm", pivot: (8.0, -6.0, 1.5), marker: RightArm, sphere: (radius: 6.0, color: (0.0, 1.0, 0.0, 0.5))),
+///         (name: "left_arm", pivot: (-8.0, -6.0, 1.5), marker: LeftArm),
+///     ],
+///     weights: [
+///         (joint: "right_arm", x: (above: 0.0)),
+///     ],
+///     default_joint: "left_arm",
+/// )
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RigDescription {
+    /// 关节列表, 顺序就是 joint index 的顺序
+    pub joints: Vec<JointDescription>,
+    /// 权重区域, 按顺序匹配, 第一个命中的区域生效
+    #[serde(default)]
+    pub weights: Vec<WeightRegion>,
+    /// 没有命中任何区域时使用的关节, 不填则使用第一个关节
+    #[serde(default)]
+    pub default_joint: Option<String>,
+    /// 权重的计算方式, 默认使用 `weights` 中的区域
+    #[serde(default)]
+    pub weight_mode: WeightMode,
+    /// 挂载点, 见 [`crate::sockets`]
+    #[serde(default)]
+    pub sockets: Vec<SocketDescription>,
+}
+
+/// 权重的计算方式
+///
+/// ```ron
+/// #![enable(unwrap_variant_newtypes)]
+/// (
+///     joints: [..],
+///     weight_mode: Distance(falloff_radius: 12.0, max_influences: 4),
+/// )
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub enum WeightMode {
+    /// 按 `weights` 区域整个绑定到一个关节
+    #[default]
+    Regions,
+    /// 按到关节的距离平滑混合
+    Distance(DistanceWeights),
+    /// 在 MagicaVoxel 里用调色板颜色绘制权重, 没有映射的颜色按 `weights` 区域处理
+    Palette(PaletteWeights),
+}
+
+/// 单个关节
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JointDescription {
+    pub name: String,
+    /// 父关节的名字, 父关节必须写在前面
+    #[serde(default)]
+    pub parent: Option<String>,
+    /// 关节在 mesh 空间中的位置 (不是相对于父关节的位置)
+    pub pivot: [f32; 3],
+    /// 静止姿态的旋转, XYZ 欧拉角 (角度)
+    #[serde(default)]
+    pub rotation: Option<[f32; 3]>,
+    /// 需要附加的标记组件
+    #[serde(default)]
+    pub marker: Option<JointMarker>,
+    /// 编辑时显示的关节球, 不填则不显示
+    #[serde(default)]
+    pub sphere: Option<JointSphere>,
+}
+
+impl JointDescription {
+    /// 关节在 mesh 空间中的静止姿态
+    pub fn rest(&self) -> Transform {
+        let mut rest = Transform::from_translation(Vec3::from(self.pivot));
+        if let Some([x, y, z]) = self.rotation {
+            rest.rotation = Quat::from_euler(
+                EulerRot::XYZ,
+                x.to_radians(),
+                y.to_radians(),
+                z.to_radians(),
+            );
+        }
+        rest
+    }
+}
+
+/// 关节上的挂载点, 挂上去的物体跟随关节运动
+///
+/// ```ron
+/// sockets: [
+///     (name: "weapon", joint: "right_hand", offset: (2.0, -4.0, 0.0), rotation: (0.0, 0.0, 90.0)),
+/// ],
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SocketDescription {
+    pub name: String,
+    pub joint: String,
+    /// 相对于关节的位置
+    #[serde(default)]
+    pub offset: [f32; 3],
+    /// 相对于关节的旋转, XYZ 欧拉角 (角度)
+    #[serde(default)]
+    pub rotation: Option<[f32; 3]>,
+}
+
+impl SocketDescription {
+    /// 相对于关节的变换
+    pub fn local(&self) -> Transform {
+        let mut local = Transform::from_translation(Vec3::from(self.offset));
+        if let Some([x, y, z]) = self.rotation {
+            local.rotation = Quat::from_euler(
+                EulerRot::XYZ,
+                x.to_radians(),
+                y.to_radians(),
+                z.to_radians(),
+            );
+        }
+        local
+    }
+}
+
+/// 可以附加到关节上的标记组件
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JointMarker {
+    LeftArm,
+    RightArm,
+    LeftLeg,
+    RightLeg,
+    LeftHand,
+    RightHand,
+    Body,
+}
+
+impl JointMarker {
+    pub fn insert(&self, entity: &mut EntityCommands<'_, '_, '_>) {
+        match self {
+            JointMarker::LeftArm => entity.insert(LeftArm),
+            JointMarker::RightArm => entity.insert(RightArm),
+            JointMarker::LeftLeg => entity.insert(LeftLeg),
+            JointMarker::RightLeg => entity.insert(RightLeg),
+            JointMarker::LeftHand => entity.insert(LeftHand),
+            JointMarker::RightHand => entity.insert(RightHand),
+            JointMarker::Body => entity.insert(Body),
+        };
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JointSphere {
+    pub radius: f32,
+    pub color: [f32; 4],
+}
+
+impl JointSphere {
+    pub fn color(&self) -> Color {
+        let [r, g, b, a] = self.color;
+        Color::rgba(r, g, b, a)
+    }
+}
+
+/// 权重区域: 顶点在所有给出的轴范围之内时整个绑定到 `joint`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WeightRegion {
+    pub joint: String,
+    #[serde(default)]
+    pub x: Option<AxisRange>,
+    #[serde(default)]
+    pub y: Option<AxisRange>,
+    #[serde(default)]
+    pub z: Option<AxisRange>,
+}
+
+impl WeightRegion {
+    pub fn contains(&self, position: Vec3) -> bool {
+        [
+            (&self.x, position.x),
+            (&self.y, position.y),
+            (&self.z, position.z),
+        ]
+        .iter()
+        .all(|(range, v)| range.as_ref().is_none_or(|r| r.contains(*v)))
+    }
+}
+
+/// 单个轴上的开区间, `above < v < below`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AxisRange {
+    #[serde(default)]
+    pub above: Option<f32>,
+    #[serde(default)]
+    pub below: Option<f32>,
+}
+
+impl AxisRange {
+    pub fn contains(&self, v: f32) -> bool {
+        self.above.is_none_or(|a| v > a) && self.below.is_none_or(|b| v < b)
+    }
+}
+
+impl RigDescription {
+    pub fn from_ron(s: &str) -> Result<Self, ron::error::SpannedError> {
+        ron::from_str(s)
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(s)
+    }
+
+    pub fn joint_index(&self, name: &str) -> Option<usize> {
+        self.joints.iter().position(|j| j.name == name)
+    }
+
+    /// 检查所有引用的关节名字都存在
+    pub fn validate(&self) -> Result<(), VoxAnimError> {
+        if self.joints.is_empty() {
+            return Err(VoxAnimError::EmptyRig);
+        }
+        let parents = self.joints.iter().filter_map(|joint| joint.parent.as_ref());
+        let regions = self.weights.iter().map(|region| &region.joint);
+        let sockets = self.sockets.iter().map(|socket| &socket.joint);
+        let palette: Vec<&String> = match &self.weight_mode {
+            WeightMode::Palette(palette) => palette.joints.values().collect(),
+            _ => Vec::new(),
+        };
+        for name in parents
+            .chain(regions)
+            .chain(self.default_joint.iter())
+            .chain(palette)
+            .chain(sockets)
+        {
+            if self.joint_index(name).is_none() {
+                return Err(VoxAnimError::MissingJoint(name.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// 按照描述构建骨骼, 有父关节的关节会转换成相对于父关节的姿态
+    pub fn skeleton_builder(&self) -> SkeletonBuilder {
+        let mut builder = SkeletonBuilder::new();
+        let mut globals: Vec<Mat4> = Vec::with_capacity(self.joints.len());
+        for (index, joint) in self.joints.iter().enumerate() {
+            let rest = joint.rest();
+            let parent = joint.parent.as_ref().and_then(|name| {
+                let parent = self.joint_index(name).filter(|parent| *parent < index);
+                if parent.is_none() {
+                    warn!(
+                        "joint {}: parent {} must be declared before it, treated as root",
+                        joint.name, name
+                    );
+                }
+                parent
+            });
+            match parent {
+                Some(parent) => {
+                    let local = globals[parent].inverse() * rest.compute_matrix();
+                    builder.add_child_joint(
+                        parent,
+                        joint.name.clone(),
+                        Transform::from_matrix(local),
+                    );
+                }
+                None => {
+                    builder.add_joint(joint.name.clone(), rest);
+                }
+            }
+            globals.push(rest.compute_matrix());
+        }
+        builder
+    }
+
+    /// 顶点的 joint index 和权重
+    /// `palette_index` 是顶点颜色对应的调色板序号, 只有 [`WeightMode::Palette`] 使用
+    /// `joint_positions` 是静止姿态下关节在 mesh 空间中的位置
+    pub fn vertex_weights(
+        &self,
+        position: Vec3,
+        palette_index: Option<u8>,
+        joint_positions: &[Vec3],
+    ) -> VertexWeights {
+        match &self.weight_mode {
+            WeightMode::Regions => single_joint(self.joint_for(position)),
+            WeightMode::Distance(distance) => distance.weights_for(position, joint_positions),
+            WeightMode::Palette(palette) => single_joint(
+                palette_index
+                    .and_then(|index| palette.joints.get(&index))
+                    .and_then(|name| self.joint_index(name))
+                    .unwrap_or_else(|| self.joint_for(position)),
+            ),
+        }
+    }
+
+    /// 顶点对应的关节 index
+    pub fn joint_for(&self, position: Vec3) -> usize {
+        self.weights
+            .iter()
+            .find(|region| region.contains(position))
+            .and_then(|region| self.joint_index(&region.joint))
+            .or_else(|| {
+                self.default_joint
+                    .as_ref()
+                    .and_then(|name| self.joint_index(name))
+            })
+            .unwrap_or(0)
+    }
+}
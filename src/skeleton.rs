@@ -0,0 +1,211 @@
+// 骨骼构建
+// 每个关节只需要给出一次静止姿态, inverse bindpose 和关节的 Transform 都从它计算出来
+
+use bevy::{
+    prelude::{
+        Assets, BuildChildren, Commands, Entity, Handle, Mat4, Name, SpatialBundle, Transform,
+    },
+    render::mesh::skinning::{SkinnedMesh, SkinnedMeshInverseBindposes},
+};
+
+use crate::types::Joint;
+
+/// 单个关节的静止姿态
+/// `rest` 相对于父关节, 没有父关节时相对于 skinned mesh 所在的 entity
+#[derive(Debug, Clone)]
+pub struct JointRest {
+    pub name: String,
+    pub parent: Option<usize>,
+    pub rest: Transform,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SkeletonBuilder {
+    joints: Vec<JointRest>,
+}
+
+/// 构建完成的骨骼, `joints` 的顺序和 joint index 一致
+/// `roots` 是没有父关节的关节, 需要作为 skinned mesh 的子节点
+#[derive(Debug, Clone)]
+pub struct Skeleton {
+    pub joints: Vec<Entity>,
+    pub roots: Vec<Entity>,
+    pub inverse_bindposes: Handle<SkinnedMeshInverseBindposes>,
+}
+
+impl SkeletonBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 添加一个根关节, 返回它的 joint index
+    pub fn add_joint(&mut self, name: impl Into<String>, rest: Transform) -> usize {
+        self.joints.push(JointRest {
+            name: name.into(),
+            parent: None,
+            rest,
+        });
+        self.joints.len() - 1
+    }
+
+    /// 在 `parent` 下添加一个子关节, `rest` 相对于父关节
+    /// 父关节必须先添加
+    pub fn add_child_joint(
+        &mut self,
+        parent: usize,
+        name: impl Into<String>,
+        rest: Transform,
+    ) -> usize {
+        assert!(
+            parent < self.joints.len(),
+            "parent joint {} must be added before its children",
+            parent
+        );
+        self.joints.push(JointRest {
+            name: name.into(),
+            parent: Some(parent),
+            rest,
+        });
+        self.joints.len() - 1
+    }
+
+    pub fn joints(&self) -> &[JointRest] {
+        &self.joints
+    }
+
+    pub fn len(&self) -> usize {
+        self.joints.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.joints.is_empty()
+    }
+
+    /// 静止姿态下每个关节相对于 skinned mesh 的矩阵, 沿着父关节链计算
+    pub fn rest_matrices(&self) -> Vec<Mat4> {
+        let mut matrices: Vec<Mat4> = Vec::with_capacity(self.joints.len());
+        for joint in self.joints.iter() {
+            let local = joint.rest.compute_matrix();
+            let global = match joint.parent {
+                Some(parent) => matrices[parent] * local,
+                None => local,
+            };
+            matrices.push(global);
+        }
+        matrices
+    }
+
+    pub fn inverse_bindposes(&self) -> Vec<Mat4> {
+        self.rest_matrices()
+            .iter()
+            .map(|matrix| matrix.inverse())
+            .collect()
+    }
+
+    /// 生成关节 entity 和 inverse bindposes
+    /// `owner` 是使用这个骨骼的 skinned mesh entity, 会记录在每个关节的 [`Joint`] 上
+    /// `roots` 需要作为 skinned mesh 的子节点, 见 [`Skeleton::skinned_mesh`]
+    pub fn build(
+        &self,
+        commands: &mut Commands,
+        owner: Entity,
+        skinned_mesh_inverse_bindposes_assets: &mut Assets<SkinnedMeshInverseBindposes>,
+    ) -> Skeleton {
+        let inverse_bindposes = skinned_mesh_inverse_bindposes_assets
+            .add(SkinnedMeshInverseBindposes::from(self.inverse_bindposes()));
+        self.spawn_joints(commands, owner, inverse_bindposes)
+    }
+
+    /// 和 [`SkeletonBuilder::build`] 一样, 但是使用已经生成的 inverse bindposes
+    pub fn spawn_joints(
+        &self,
+        commands: &mut Commands,
+        owner: Entity,
+        inverse_bindposes: Handle<SkinnedMeshInverseBindposes>,
+    ) -> Skeleton {
+        let joints: Vec<Entity> = self
+            .joints
+            .iter()
+            .enumerate()
+            .map(|(index, joint)| {
+                commands
+                    .spawn((
+                        Name::new(joint.name.clone()),
+                        Joint {
+                            name: joint.name.clone(),
+                            index,
+                            skeleton: owner,
+                        },
+                        SpatialBundle::from_transform(joint.rest),
+                    ))
+                    .id()
+            })
+            .collect();
+        let mut roots = Vec::new();
+        for (joint, entity) in self.joints.iter().zip(joints.iter()) {
+            match joint.parent {
+                Some(parent) => {
+                    commands.entity(joints[parent]).add_child(*entity);
+                }
+                None => roots.push(*entity),
+            }
+        }
+        Skeleton {
+            joints,
+            roots,
+            inverse_bindposes,
+        }
+    }
+}
+
+impl Skeleton {
+    pub fn skinned_mesh(&self) -> SkinnedMesh {
+        SkinnedMesh {
+            inverse_bindposes: self.inverse_bindposes.clone(),
+            joints: self.joints.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::prelude::{Quat, Vec3};
+
+    #[test]
+    fn inverse_bindposes_undo_rest_pose() {
+        let mut builder = SkeletonBuilder::new();
+        builder.add_joint("body", Transform::from_xyz(0.0, 4.0, 0.0));
+        builder.add_joint(
+            "arm",
+            Transform::from_xyz(2.0, 0.0, 0.0).with_rotation(Quat::from_rotation_z(1.0)),
+        );
+        let inverse_bindposes = builder.inverse_bindposes();
+        assert_eq!(inverse_bindposes.len(), 2);
+        for (inverse, rest) in inverse_bindposes.iter().zip(builder.rest_matrices()) {
+            assert!((*inverse * rest).abs_diff_eq(Mat4::IDENTITY, 1e-5));
+        }
+        // 关节位置上的顶点变换到关节空间的原点
+        let local = inverse_bindposes[0].transform_point3(Vec3::new(0.0, 4.0, 0.0));
+        assert!(local.abs_diff_eq(Vec3::ZERO, 1e-5));
+    }
+
+    #[test]
+    fn child_rest_is_relative_to_parent() {
+        let mut builder = SkeletonBuilder::new();
+        let body = builder.add_joint("body", Transform::from_xyz(0.0, 4.0, 0.0));
+        let arm = builder.add_child_joint(body, "arm", Transform::from_xyz(2.0, 0.0, 0.0));
+        builder.add_child_joint(arm, "hand", Transform::from_xyz(3.0, 0.0, 0.0));
+
+        let rest = builder.rest_matrices();
+        assert_eq!(rest[2].w_axis.truncate(), Vec3::new(5.0, 4.0, 0.0));
+        let local = builder.inverse_bindposes()[2].transform_point3(Vec3::new(5.0, 4.0, 0.0));
+        assert!(local.abs_diff_eq(Vec3::ZERO, 1e-5));
+    }
+
+    #[test]
+    #[should_panic]
+    fn parent_must_be_added_first() {
+        SkeletonBuilder::new().add_child_joint(0, "arm", Transform::IDENTITY);
+    }
+}
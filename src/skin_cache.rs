@@ -0,0 +1,53 @@
+// 生成结果的缓存
+// 同一个模型用同一个骨骼生成多次时, 共用 mesh 和 bindposes, 只生成新的关节 entity
+
+use bevy::{
+    asset::HandleId,
+    prelude::{Handle, Mesh, StandardMaterial},
+    render::mesh::skinning::SkinnedMeshInverseBindposes,
+    utils::HashMap,
+};
+use std::sync::{Arc, Mutex};
+
+/// 一个模型的生成结果
+#[derive(Debug, Clone)]
+pub struct CachedSkin {
+    pub mesh: Handle<Mesh>,
+    pub inverse_bindposes: Handle<SkinnedMeshInverseBindposes>,
+    /// 每个关节的球, 和关节顺序一致
+    pub spheres: Vec<Option<(Handle<Mesh>, Handle<StandardMaterial>)>>,
+}
+
+/// 按源 mesh 缓存生成结果
+/// clone 出来的 dealer 共用同一个缓存, 所以同一个 `.voxrig` 生成的角色之间会共享
+#[derive(Debug, Clone, Default)]
+pub struct SkinCache {
+    entries: Arc<Mutex<HashMap<HandleId, CachedSkin>>>,
+}
+
+impl SkinCache {
+    pub fn get(&self, source: &Handle<Mesh>) -> Option<CachedSkin> {
+        self.entries.lock().ok()?.get(&source.id()).cloned()
+    }
+
+    pub fn insert(&self, source: &Handle<Mesh>, skin: CachedSkin) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.insert(source.id(), skin);
+        }
+    }
+
+    /// 源 mesh 修改之后需要清空
+    pub fn clear(&self) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.clear();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().map_or(0, |entries| entries.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
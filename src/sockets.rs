@@ -0,0 +1,295 @@
+// 关节上的挂载点
+// 剑, 帽子或者背包挂到挂载点上之后作为关节的子节点, 跟随骨骼动画运动
+
+use bevy::{
+    ecs::system::SystemParam,
+    log::warn,
+    prelude::{
+        App, BuildChildren, Children, Commands, Component, Entity, Event, EventReader, Local, Name,
+        Plugin, Query, SpatialBundle, Update, With,
+    },
+    transform::commands::BuildChildrenTransformExt,
+};
+
+use crate::{
+    character::{VoxCharacter, VoxCharacterError},
+    rig::SocketDescription,
+};
+
+/// 挂载点, 是关节的子节点
+#[derive(Debug, Clone, Component)]
+pub struct Socket {
+    pub name: String,
+}
+
+/// 挂在挂载点上的物体
+#[derive(Debug, Clone, Component)]
+pub struct Attached {
+    pub character: Entity,
+    pub socket: String,
+}
+
+/// 角色的层级, 遍历时跳过挂载的物体和它的子节点
+/// 挂上去的物体不属于角色, 它的关节, 图层, 关键帧和挂载点都不算在角色里
+#[derive(SystemParam)]
+pub struct CharacterHierarchy<'w, 's> {
+    children: Query<'w, 's, &'static Children>,
+    attached: Query<'w, 's, (), With<Attached>>,
+}
+
+impl CharacterHierarchy<'_, '_> {
+    /// 不包括挂载的物体
+    pub fn children(&self, entity: Entity) -> impl Iterator<Item = Entity> + '_ {
+        self.children
+            .get(entity)
+            .into_iter()
+            .flat_map(|children| children.iter().copied())
+            .filter(|child| !self.attached.contains(*child))
+    }
+
+    /// `root` 和它的子孙节点, 父节点在前, 兄弟节点按顺序
+    /// `root` 自己挂在别的角色上时也会遍历
+    pub fn descendants(&self, root: Entity) -> Vec<Entity> {
+        let mut result = Vec::new();
+        let mut stack = vec![root];
+        while let Some(entity) = stack.pop() {
+            result.push(entity);
+            let start = stack.len();
+            stack.extend(self.children(entity));
+            stack[start..].reverse();
+        }
+        result
+    }
+}
+
+/// 生成挂载点 entity 并放到关节下面
+pub fn spawn_socket(commands: &mut Commands, joint: Entity, socket: &SocketDescription) -> Entity {
+    let entity = commands
+        .spawn((
+            Socket {
+                name: socket.name.clone(),
+            },
+            Name::new(format!("socket:{}", socket.name)),
+            SpatialBundle::from_transform(socket.local()),
+        ))
+        .id();
+    commands.entity(joint).add_child(entity);
+    entity
+}
+
+/// 在角色下按名字找到挂载点, 不包括挂载的物体上的挂载点
+pub fn find_socket(
+    character: Entity,
+    name: &str,
+    hierarchy: &CharacterHierarchy,
+    socket_query: &Query<&Socket>,
+) -> Option<Entity> {
+    hierarchy.descendants(character).into_iter().find(|entity| {
+        socket_query
+            .get(*entity)
+            .is_ok_and(|socket| socket.name == name)
+    })
+}
+
+/// 把 `item` 挂到角色的挂载点上, `item` 的 Transform 是相对于挂载点的
+/// 角色还没有生成完成时会等到生成之后再挂载
+#[derive(Debug, Clone, Event)]
+pub struct AttachToSocket {
+    pub character: Entity,
+    pub socket: String,
+    pub item: Entity,
+}
+
+/// 从挂载点上取下, 保持当前的世界坐标
+#[derive(Debug, Clone, Event)]
+pub struct DetachFromSocket {
+    pub item: Entity,
+}
+
+/// 由 [`crate::character::VoxCharacterPlugin`] 添加
+pub struct VoxSocketsPlugin;
+
+impl Plugin for VoxSocketsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<AttachToSocket>()
+            .add_event::<DetachFromSocket>()
+            .add_systems(Update, (attach_items, detach_items));
+    }
+}
+
+pub(crate) fn attach_items(
+    mut commands: Commands,
+    mut events: EventReader<AttachToSocket>,
+    mut pending: Local<Vec<AttachToSocket>>,
+    characters: Query<(Option<&VoxCharacter>, Option<&VoxCharacterError>)>,
+    hierarchy: CharacterHierarchy,
+    socket_query: Query<&Socket>,
+) {
+    let requests: Vec<AttachToSocket> = pending.drain(..).chain(events.iter().cloned()).collect();
+    for request in requests {
+        let Ok((character, failed)) = characters.get(request.character) else {
+            warn!(
+                "{:?} does not exist, {:?} not attached",
+                request.character, request.item
+            );
+            continue;
+        };
+        if failed.is_some() {
+            continue;
+        }
+        if character.is_none() {
+            // 角色还在加载
+            pending.push(request);
+            continue;
+        }
+        let Some(socket) = find_socket(
+            request.character,
+            &request.socket,
+            &hierarchy,
+            &socket_query,
+        ) else {
+            warn!(
+                "{:?}: socket {} not found",
+                request.character, request.socket
+            );
+            continue;
+        };
+        let Some(mut item) = commands.get_entity(request.item) else {
+            continue;
+        };
+        item.insert(Attached {
+            character: request.character,
+            socket: request.socket,
+        })
+        .set_parent(socket);
+    }
+}
+
+fn detach_items(
+    mut commands: Commands,
+    mut events: EventReader<DetachFromSocket>,
+    attached: Query<(), With<Attached>>,
+) {
+    for event in events.iter() {
+        if !attached.contains(event.item) {
+            continue;
+        }
+        commands
+            .entity(event.item)
+            .remove::<Attached>()
+            .remove_parent_in_place();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        joint_map::{JointMap, JointMapPlugin},
+        layers::{NodeVisibility, VoxLayers, VoxLayersPlugin},
+        types::Joint,
+        vox_animation::{VoxAnimationPlayer, VoxAnimationPlugin, VoxKeyframes},
+    };
+    use bevy::{
+        ecs::system::SystemState,
+        prelude::{BuildWorldChildren, Time, Transform},
+        utils::HashMap,
+    };
+    use bevy_vox_mesh::vox_scene_info::LayerData;
+
+    #[test]
+    fn attached_items_are_not_part_of_the_character() {
+        let mut app = App::new();
+        app.init_resource::<Time>().add_plugins((
+            JointMapPlugin,
+            VoxLayersPlugin,
+            VoxAnimationPlugin,
+        ));
+
+        let host = app
+            .world
+            .spawn((
+                JointMap::default(),
+                VoxLayers::from_layer_map(&HashMap::from_iter([(1, true)])),
+                VoxCharacter {
+                    parts: HashMap::new(),
+                },
+                VoxAnimationPlayer {
+                    playing: false,
+                    ..Default::default()
+                },
+            ))
+            .id();
+        let joint = app
+            .world
+            .spawn(Joint {
+                name: "hand".to_string(),
+                index: 0,
+                skeleton: host,
+            })
+            .id();
+        let socket = app
+            .world
+            .spawn(Socket {
+                name: "hand".to_string(),
+            })
+            .id();
+        app.world.entity_mut(host).add_child(joint);
+        app.world.entity_mut(joint).add_child(socket);
+
+        // 挂上去的物体自己带有关节, 图层, 关键帧和同名的挂载点
+        let item = app
+            .world
+            .spawn((
+                Attached {
+                    character: host,
+                    socket: "hand".to_string(),
+                },
+                Joint {
+                    name: "blade".to_string(),
+                    index: 0,
+                    skeleton: host,
+                },
+            ))
+            .id();
+        let item_node = app
+            .world
+            .spawn((
+                Socket {
+                    name: "hand".to_string(),
+                },
+                LayerData(1),
+                NodeVisibility::default(),
+                VoxKeyframes {
+                    keys: vec![(0, Transform::IDENTITY), (10, Transform::IDENTITY)],
+                },
+                Transform::from_xyz(5.0, 5.0, 5.0),
+            ))
+            .id();
+        app.world.entity_mut(socket).add_child(item);
+        app.world.entity_mut(item).add_child(item_node);
+        app.update();
+
+        let joint_map = app.world.get::<JointMap>(host).unwrap();
+        assert_eq!(joint_map.get("hand"), Some(joint));
+        assert_eq!(joint_map.get("blade"), None);
+        assert!(
+            !app.world
+                .get::<NodeVisibility>(item_node)
+                .unwrap()
+                .layer_hidden
+        );
+        assert_eq!(
+            app.world.get::<Transform>(item_node).unwrap().translation,
+            Transform::from_xyz(5.0, 5.0, 5.0).translation
+        );
+
+        let mut state: SystemState<(CharacterHierarchy, Query<&Socket>)> =
+            SystemState::new(&mut app.world);
+        let (hierarchy, sockets) = state.get(&app.world);
+        assert_eq!(
+            find_socket(host, "hand", &hierarchy, &sockets),
+            Some(socket)
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use bevy::prelude::Component;
+use bevy::prelude::{Component, Entity};
 
 #[derive(Debug, Component)]
 pub struct LeftArm;
@@ -23,3 +23,18 @@ pub struct RightHand;
 
 #[derive(Debug, Component)]
 pub struct Body;
+
+/// 刚性绑定的部件, 整个模型跟着这个关节运动
+#[derive(Debug, Component)]
+pub struct RigidPart;
+
+/// 通用的关节, 按名字查找, 不再需要为每个部位定义一个类型
+#[derive(Debug, Clone, Component)]
+pub struct Joint {
+    pub name: String,
+    /// 在 `SkinnedMesh::joints` 中的序号, 刚性绑定的关节是 0
+    pub index: usize,
+    /// 使用这个关节的 skinned mesh entity
+    /// 刚性绑定的关节 ([`RigidPart`]) 没有 skinned mesh, 是关节自己
+    pub skeleton: Entity,
+}
@@ -0,0 +1,311 @@
+// 蒙皮数据检查
+// 归一化权重, 并找出会导致顶点塌缩到原点的数据
+
+use bevy::{
+    prelude::{
+        warn, App, Assets, Commands, Component, Entity, Handle, Mesh, Plugin, Query, Res, ResMut,
+        Update, Without,
+    },
+    render::mesh::{
+        skinning::{SkinnedMesh, SkinnedMeshInverseBindposes},
+        VertexAttributeValues,
+    },
+};
+use thiserror::Error;
+
+/// 权重之和和 1 的差小于这个值时不再归一化
+const WEIGHT_EPSILON: f32 = 1e-4;
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum SkinError {
+    #[error("mesh has no joint index or joint weight attribute")]
+    MissingJointAttributes,
+    #[error("joint attributes must be Uint16x4 / Float32x4")]
+    UnsupportedJointFormat,
+    #[error("vertex count mismatch: {positions} positions, {indices} joint indices, {weights} joint weights")]
+    VertexCountMismatch {
+        positions: usize,
+        indices: usize,
+        weights: usize,
+    },
+    #[error("vertex {vertex} has zero total weight")]
+    ZeroWeight { vertex: usize },
+    #[error("vertex {vertex} uses joint {joint} but there are only {joint_count} joints")]
+    JointIndexOutOfRange {
+        vertex: usize,
+        joint: u16,
+        joint_count: usize,
+    },
+    #[error("{bindposes} inverse bindposes for {joints} joints")]
+    BindposeCountMismatch { bindposes: usize, joints: usize },
+}
+
+/// 检查结果
+#[derive(Debug, Clone, Default)]
+pub struct SkinReport {
+    /// 被重新归一化的顶点数量
+    pub normalized: usize,
+    pub errors: Vec<SkinError>,
+}
+
+impl SkinReport {
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// 输出所有的问题, 有错误时返回第一个
+    pub fn log_result(&self, name: &str) -> Result<(), SkinError> {
+        self.log(name);
+        match self.errors.first() {
+            Some(error) => Err(error.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// 通过 `bevy::log` 输出所有的问题
+    pub fn log(&self, name: &str) {
+        if self.normalized > 0 {
+            warn!(
+                "{}: normalized weights of {} vertices",
+                name, self.normalized
+            );
+        }
+        for error in self.errors.iter() {
+            warn!("{}: {}", name, error);
+        }
+    }
+}
+
+/// 检查一个 skinned mesh, 权重之和不为 1 的顶点会被直接归一化
+/// `Mesh::insert_attribute` 会把全为 0 的权重改成第一个关节, 自己生成权重时在写入之前用 [`validate_weights`] 检查
+pub fn validate_skin(mesh: &mut Mesh, joint_count: usize, bindpose_count: usize) -> SkinReport {
+    let (report, normalized) = inspect_skin(mesh, joint_count, bindpose_count);
+    if let Some(weights) = normalized {
+        mesh.insert_attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT, weights);
+    }
+    report
+}
+
+/// 和 [`validate_skin`] 一样检查, 但是不修改 mesh, 有顶点需要归一化时返回归一化之后的权重
+pub fn inspect_skin(
+    mesh: &Mesh,
+    joint_count: usize,
+    bindpose_count: usize,
+) -> (SkinReport, Option<Vec<[f32; 4]>>) {
+    let mut report = SkinReport::default();
+    if bindpose_count != joint_count {
+        report.errors.push(SkinError::BindposeCountMismatch {
+            bindposes: bindpose_count,
+            joints: joint_count,
+        });
+    }
+    let (indices, mut weights) = match joint_attributes(mesh) {
+        Ok(attributes) => attributes,
+        Err(error) => {
+            report.errors.push(error);
+            return (report, None);
+        }
+    };
+    let weights_report = validate_weights(&indices, &mut weights, joint_count);
+    report.normalized = weights_report.normalized;
+    report.errors.extend(weights_report.errors);
+    let normalized = (report.normalized > 0).then_some(weights);
+    (report, normalized)
+}
+
+/// 检查还没有写入 mesh 的权重, 权重之和不为 1 的顶点会被直接归一化
+pub fn validate_weights(
+    indices: &[[u16; 4]],
+    weights: &mut [[f32; 4]],
+    joint_count: usize,
+) -> SkinReport {
+    let mut report = SkinReport::default();
+    if indices.len() != weights.len() {
+        report.errors.push(SkinError::VertexCountMismatch {
+            positions: weights.len(),
+            indices: indices.len(),
+            weights: weights.len(),
+        });
+        return report;
+    }
+    for (vertex, (joints, weight)) in indices.iter().zip(weights.iter_mut()).enumerate() {
+        for (joint, w) in joints.iter().zip(weight.iter()) {
+            if *w > 0.0 && *joint as usize >= joint_count {
+                report.errors.push(SkinError::JointIndexOutOfRange {
+                    vertex,
+                    joint: *joint,
+                    joint_count,
+                });
+            }
+        }
+        let total: f32 = weight.iter().sum();
+        if total <= 0.0 {
+            report.errors.push(SkinError::ZeroWeight { vertex });
+        } else if (total - 1.0).abs() > WEIGHT_EPSILON {
+            for w in weight.iter_mut() {
+                *w /= total;
+            }
+            report.normalized += 1;
+        }
+    }
+    report
+}
+
+// 读取关节属性并检查格式和顶点数量
+type JointAttributes = (Vec<[u16; 4]>, Vec<[f32; 4]>);
+
+fn joint_attributes(mesh: &Mesh) -> Result<JointAttributes, SkinError> {
+    let indices = match mesh.attribute(Mesh::ATTRIBUTE_JOINT_INDEX) {
+        Some(VertexAttributeValues::Uint16x4(indices)) => indices.clone(),
+        Some(_) => return Err(SkinError::UnsupportedJointFormat),
+        None => return Err(SkinError::MissingJointAttributes),
+    };
+    let weights = match mesh.attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT) {
+        Some(VertexAttributeValues::Float32x4(weights)) => weights.clone(),
+        Some(_) => return Err(SkinError::UnsupportedJointFormat),
+        None => return Err(SkinError::MissingJointAttributes),
+    };
+    let positions = mesh.count_vertices();
+    if indices.len() != positions || weights.len() != positions {
+        return Err(SkinError::VertexCountMismatch {
+            positions,
+            indices: indices.len(),
+            weights: weights.len(),
+        });
+    }
+    Ok((indices, weights))
+}
+
+/// 检查所有生成出来的 [`SkinnedMesh`], 包括自己实现 `DealWithJoints` 生成的
+/// 由 [`crate::character::VoxCharacterPlugin`] 添加
+pub struct SkinValidationPlugin;
+
+impl Plugin for SkinValidationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, validate_skinned_meshes);
+    }
+}
+
+// 已经检查过的 entity
+#[derive(Component)]
+struct SkinChecked;
+
+// mesh 和 bindposes 都加载完之后检查一次
+// 关节数量以实际挂上的 `SkinnedMesh::joints` 为准, 只有需要归一化时才修改 mesh
+fn validate_skinned_meshes(
+    mut commands: Commands,
+    skinned: Query<(Entity, &SkinnedMesh, &Handle<Mesh>), Without<SkinChecked>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    bindposes: Res<Assets<SkinnedMeshInverseBindposes>>,
+) {
+    for (entity, skin, handle) in skinned.iter() {
+        let (Some(mesh), Some(inverse_bindposes)) =
+            (meshes.get(handle), bindposes.get(&skin.inverse_bindposes))
+        else {
+            continue;
+        };
+        let (report, normalized) = inspect_skin(mesh, skin.joints.len(), inverse_bindposes.len());
+        if let (Some(weights), Some(mesh)) = (normalized, meshes.get_mut(handle)) {
+            mesh.insert_attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT, weights);
+        }
+        report.log(&format!("skinned mesh {:?}", entity));
+        commands.entity(entity).insert(SkinChecked);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::render_resource::PrimitiveTopology;
+
+    fn skinned_mesh(indices: Vec<[u16; 4]>, weights: Vec<[f32; 4]>) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vec![[0.0f32; 3]; indices.len()]);
+        mesh.insert_attribute(
+            Mesh::ATTRIBUTE_JOINT_INDEX,
+            VertexAttributeValues::Uint16x4(indices),
+        );
+        mesh.insert_attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT, weights);
+        mesh
+    }
+
+    fn joint_weights(mesh: &Mesh) -> Vec<[f32; 4]> {
+        match mesh.attribute(Mesh::ATTRIBUTE_JOINT_WEIGHT) {
+            Some(VertexAttributeValues::Float32x4(weights)) => weights.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn valid_skin() {
+        let mut mesh = skinned_mesh(vec![[0, 1, 0, 0]], vec![[0.25, 0.75, 0.0, 0.0]]);
+        let report = validate_skin(&mut mesh, 2, 2);
+        assert!(report.is_ok());
+        assert_eq!(report.normalized, 0);
+    }
+
+    #[test]
+    fn normalizes_weights() {
+        let mut mesh = skinned_mesh(
+            vec![[0, 1, 0, 0], [0, 0, 0, 0]],
+            vec![[1.0, 3.0, 0.0, 0.0], [1.0, 0.0, 0.0, 0.0]],
+        );
+        let report = validate_skin(&mut mesh, 2, 2);
+        assert!(report.is_ok());
+        assert_eq!(report.normalized, 1);
+        assert_eq!(joint_weights(&mesh)[0], [0.25, 0.75, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn reports_bad_weights() {
+        // 写入 mesh 之前检查, 全为 0 的权重还没有被 insert_attribute 改掉
+        let indices = vec![[0, 0, 0, 0], [0, 5, 0, 0]];
+        let mut weights = vec![[0.0; 4], [0.5, 0.5, 0.0, 0.0]];
+        let report = validate_weights(&indices, &mut weights, 2);
+        assert_eq!(
+            report.errors,
+            vec![
+                SkinError::ZeroWeight { vertex: 0 },
+                SkinError::JointIndexOutOfRange {
+                    vertex: 1,
+                    joint: 5,
+                    joint_count: 2
+                },
+            ]
+        );
+        assert_eq!(
+            report.log_result("test"),
+            Err(SkinError::ZeroWeight { vertex: 0 })
+        );
+    }
+
+    #[test]
+    fn reports_bindpose_mismatch() {
+        let mut mesh = skinned_mesh(vec![[0, 1, 0, 0]], vec![[0.5, 0.5, 0.0, 0.0]]);
+        let report = validate_skin(&mut mesh, 2, 1);
+        assert_eq!(
+            report.errors,
+            vec![SkinError::BindposeCountMismatch {
+                bindposes: 1,
+                joints: 2
+            }]
+        );
+    }
+
+    #[test]
+    fn inspect_does_not_modify_mesh() {
+        let mesh = skinned_mesh(vec![[0, 1, 0, 0]], vec![[2.0, 2.0, 0.0, 0.0]]);
+        let (report, normalized) = inspect_skin(&mesh, 2, 2);
+        assert_eq!(report.normalized, 1);
+        assert_eq!(normalized, Some(vec![[0.5, 0.5, 0.0, 0.0]]));
+        assert_eq!(joint_weights(&mesh)[0], [2.0, 2.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn missing_joint_attributes() {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, vec![[0.0f32; 3]; 3]);
+        let report = validate_skin(&mut mesh, 1, 1);
+        assert_eq!(report.errors, vec![SkinError::MissingJointAttributes]);
+    }
+}
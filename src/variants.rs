@@ -0,0 +1,134 @@
+// 互斥的部件, 例如表情和发型
+// 每组同时只显示一个, 切换只影响这个角色自己的节点
+
+use bevy::{
+    log::warn,
+    prelude::{
+        App, Changed, Component, Entity, Event, EventReader, IntoSystemConfigs, Or, Plugin, Query,
+        Update,
+    },
+    utils::HashMap,
+};
+
+use crate::{character::VoxCharacter, layers::NodeVisibility};
+
+/// 放在角色根节点上, 记录每组当前显示的部件
+/// 部件名字就是 [`VoxCharacter::parts`] 中的 key
+#[derive(Debug, Clone, Default, Component)]
+pub struct VariantState {
+    groups: HashMap<String, Vec<String>>,
+    active: HashMap<String, String>,
+}
+
+impl VariantState {
+    /// 每组默认显示第一个
+    pub fn new(groups: HashMap<String, Vec<String>>) -> Self {
+        let active = groups
+            .iter()
+            .filter_map(|(group, values)| Some((group.clone(), values.first()?.clone())))
+            .collect();
+        Self { groups, active }
+    }
+
+    pub fn groups(&self) -> impl Iterator<Item = (&String, &Vec<String>)> {
+        self.groups.iter()
+    }
+
+    pub fn active(&self, group: &str) -> Option<&str> {
+        self.active.get(group).map(|value| value.as_str())
+    }
+
+    /// `value` 不在组中时返回 `false`
+    pub fn set(&mut self, group: &str, value: &str) -> bool {
+        let Some(values) = self.groups.get(group) else {
+            return false;
+        };
+        if !values.iter().any(|v| v == value) {
+            return false;
+        }
+        self.active.insert(group.to_string(), value.to_string());
+        true
+    }
+
+    /// 组中的下一个部件, 最后一个之后回到第一个
+    pub fn next_value(&self, group: &str) -> Option<&str> {
+        let values = self.groups.get(group)?;
+        let index = self
+            .active(group)
+            .and_then(|active| values.iter().position(|v| v == active))
+            .map_or(0, |index| (index + 1) % values.len());
+        values.get(index).map(|value| value.as_str())
+    }
+
+    /// 部件是否被它所在的组隐藏
+    pub fn is_hidden(&self, part: &str) -> bool {
+        self.groups.iter().any(|(group, values)| {
+            values.iter().any(|v| v == part) && self.active(group) != Some(part)
+        })
+    }
+}
+
+/// 切换一个角色的部件
+#[derive(Debug, Clone, Event)]
+pub struct SetVariant {
+    pub character: Entity,
+    pub group: String,
+    pub value: String,
+}
+
+/// 由 [`crate::character::VoxCharacterPlugin`] 添加
+pub struct VoxVariantsPlugin;
+
+impl Plugin for VoxVariantsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SetVariant>().add_systems(
+            Update,
+            (apply_variant_events, sync_variant_visibility).chain(),
+        );
+    }
+}
+
+fn apply_variant_events(
+    mut events: EventReader<SetVariant>,
+    mut characters: Query<&mut VariantState>,
+) {
+    for event in events.iter() {
+        let Ok(mut state) = characters.get_mut(event.character) else {
+            warn!("{:?} has no VariantState", event.character);
+            continue;
+        };
+        if state.active(&event.group) == Some(event.value.as_str()) {
+            continue;
+        }
+        if !state.set(&event.group, &event.value) {
+            warn!(
+                "{:?}: {} is not a variant of {}",
+                event.character, event.value, event.group
+            );
+        }
+    }
+}
+
+type ChangedVariants<'w, 's> = Query<
+    'w,
+    's,
+    (&'static VariantState, &'static VoxCharacter),
+    Or<(Changed<VariantState>, Changed<VoxCharacter>)>,
+>;
+
+fn sync_variant_visibility(characters: ChangedVariants, mut nodes: Query<&mut NodeVisibility>) {
+    for (state, character) in characters.iter() {
+        for values in state.groups.values() {
+            for value in values {
+                let hidden = state.is_hidden(value);
+                for entity in character.parts.get(value).into_iter().flatten() {
+                    if let Ok(mut node) = nodes.get_mut(*entity) {
+                        if node.variant_hidden != hidden {
+                            node.variant_hidden = hidden;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,351 @@
+// MagicaVoxel 关键帧动画
+// nTRN 节点的 `_f` 帧变成 Transform 关键帧, nSHP 节点的多帧模型变成按帧切换显示
+
+use bevy::prelude::{
+    App, Changed, Children, Commands, Component, Entity, HierarchyQueryExt, IntoSystemConfigs,
+    Parent, Plugin, Query, Res, Time, Transform, Update, Visibility, With, Without,
+};
+use dot_vox::{Dict, Frame};
+
+use crate::{sockets::CharacterHierarchy, vox_transform::frame_transform};
+
+/// 帧序号属性
+const FRAME_ATTRIBUTE: &str = "_f";
+
+/// 读取 `_f`, 没有时是第 0 帧
+pub fn frame_index(attributes: &Dict) -> u32 {
+    attributes
+        .get(FRAME_ATTRIBUTE)
+        .and_then(|f| f.parse().ok())
+        .unwrap_or(0)
+}
+
+/// 一个 transform 节点的关键帧, 按帧序号排序
+#[derive(Debug, Clone, Component)]
+pub struct VoxKeyframes {
+    pub keys: Vec<(u32, Transform)>,
+}
+
+impl VoxKeyframes {
+    /// 少于两帧时不需要动画, 返回 `None`
+    pub fn from_frames(frames: &[Frame]) -> Option<Self> {
+        if frames.len() < 2 {
+            return None;
+        }
+        let mut keys: Vec<(u32, Transform)> = frames
+            .iter()
+            .map(|frame| (frame_index(&frame.attributes), frame_transform(frame)))
+            .collect();
+        keys.sort_by_key(|(index, _)| *index);
+        Some(Self { keys })
+    }
+
+    /// 节点单独生成时, 把祖先节点的静止姿态乘到每一帧上
+    pub fn with_parent(mut self, parent: Transform) -> Self {
+        for (_, key) in self.keys.iter_mut() {
+            *key = parent * *key;
+        }
+        self
+    }
+
+    pub fn last_frame(&self) -> u32 {
+        self.keys.last().map_or(0, |(index, _)| *index)
+    }
+
+    /// 在两个关键帧之间插值
+    pub fn sample(&self, frame: f32) -> Transform {
+        let next = self
+            .keys
+            .iter()
+            .position(|(index, _)| *index as f32 > frame);
+        match next {
+            Some(0) => self.keys[0].1,
+            Some(next) => {
+                let (from_index, from) = self.keys[next - 1];
+                let (to_index, to) = self.keys[next];
+                let t = (frame - from_index as f32) / (to_index - from_index) as f32;
+                Transform {
+                    translation: from.translation.lerp(to.translation, t),
+                    rotation: from.rotation.slerp(to.rotation, t),
+                    scale: from.scale.lerp(to.scale, t),
+                }
+            }
+            None => self
+                .keys
+                .last()
+                .map_or(Transform::IDENTITY, |(_, key)| *key),
+        }
+    }
+}
+
+/// 多帧模型中的一个, 从 `frame` 开始显示, 直到下一个模型的帧
+#[derive(Debug, Clone, Copy, Component)]
+pub struct ModelKeyframe {
+    pub frame: u32,
+}
+
+/// 放在角色根节点上, 播放它下面所有的 vox 关键帧
+#[derive(Debug, Clone, Component)]
+pub struct VoxAnimationPlayer {
+    pub frame: f32,
+    pub fps: f32,
+    pub playing: bool,
+    pub looping: bool,
+}
+
+impl Default for VoxAnimationPlayer {
+    fn default() -> Self {
+        Self {
+            frame: 0.0,
+            fps: 10.0,
+            playing: true,
+            looping: true,
+        }
+    }
+}
+
+pub struct VoxAnimationPlugin;
+
+impl Plugin for VoxAnimationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, (collect_player_tracks, play_vox_animations).chain());
+    }
+}
+
+// 播放器下所有的关键帧, 子节点变化时重新收集
+#[derive(Component)]
+struct VoxAnimationTracks {
+    tracks: Vec<Track>,
+    length: u32,
+    // 上次写入的帧, 没有变化时不再写 Transform
+    sampled: Option<f32>,
+}
+
+impl VoxAnimationTracks {
+    fn new(tracks: Vec<Track>) -> Self {
+        let length = tracks
+            .iter()
+            .map(|track| track.last_frame)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        Self {
+            tracks,
+            length,
+            sampled: None,
+        }
+    }
+}
+
+fn collect_player_tracks(
+    mut commands: Commands,
+    players: Query<(Entity, Option<&VoxAnimationTracks>), With<VoxAnimationPlayer>>,
+    changed: Query<Entity, Changed<Children>>,
+    parents: Query<&Parent>,
+    hierarchy: CharacterHierarchy,
+    keyframes_query: Query<&VoxKeyframes>,
+    model_query: Query<&ModelKeyframe>,
+) {
+    let mut dirty: Vec<Entity> = players
+        .iter()
+        .filter(|(_, tracks)| tracks.is_none())
+        .map(|(player, _)| player)
+        .collect();
+    // 层级变化的节点所在的播放器
+    for entity in changed.iter() {
+        dirty.extend(
+            std::iter::once(entity)
+                .chain(parents.iter_ancestors(entity))
+                .filter(|ancestor| players.contains(*ancestor)),
+        );
+    }
+    dirty.sort();
+    dirty.dedup();
+
+    for player in dirty {
+        let tracks = collect_tracks(player, &hierarchy, &keyframes_query, &model_query);
+        commands
+            .entity(player)
+            .insert(VoxAnimationTracks::new(tracks));
+    }
+}
+
+fn play_vox_animations(
+    time: Res<Time>,
+    mut players: Query<(&mut VoxAnimationPlayer, &mut VoxAnimationTracks)>,
+    keyframes_query: Query<&VoxKeyframes>,
+    mut transforms: Query<&mut Transform, Without<VoxAnimationPlayer>>,
+    mut visibilities: Query<&mut Visibility, Without<VoxAnimationPlayer>>,
+) {
+    for (mut player, mut tracks) in players.iter_mut() {
+        let length = tracks.length;
+        if player.playing {
+            player.frame += time.delta_seconds() * player.fps;
+            if player.frame >= length as f32 {
+                if player.looping {
+                    player.frame %= length as f32;
+                } else {
+                    player.frame = (length - 1) as f32;
+                    player.playing = false;
+                }
+            }
+        }
+
+        let frame = player.frame;
+        if tracks.sampled == Some(frame) {
+            continue;
+        }
+        tracks.sampled = Some(frame);
+        for track in tracks.tracks.iter() {
+            match &track.kind {
+                TrackKind::Transform(entity) => {
+                    if let (Ok(keyframes), Ok(mut transform)) =
+                        (keyframes_query.get(*entity), transforms.get_mut(*entity))
+                    {
+                        *transform = keyframes.sample(frame);
+                    }
+                }
+                TrackKind::Models(models) => {
+                    let active = active_model(models, frame);
+                    for (_, entity) in models.iter() {
+                        if let Ok(mut visibility) = visibilities.get_mut(*entity) {
+                            let target = if Some(*entity) == active {
+                                Visibility::Inherited
+                            } else {
+                                Visibility::Hidden
+                            };
+                            if *visibility != target {
+                                *visibility = target;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// 当前帧之前最后一个模型, 都在当前帧之后时显示第一个
+fn active_model(models: &[(u32, Entity)], frame: f32) -> Option<Entity> {
+    models
+        .iter()
+        .filter(|(key, _)| *key as f32 <= frame)
+        .max_by_key(|(key, _)| *key)
+        .or_else(|| models.iter().min_by_key(|(key, _)| *key))
+        .map(|(_, entity)| *entity)
+}
+
+enum TrackKind {
+    Transform(Entity),
+    Models(Vec<(u32, Entity)>),
+}
+
+struct Track {
+    kind: TrackKind,
+    last_frame: u32,
+}
+
+fn collect_tracks(
+    root: Entity,
+    hierarchy: &CharacterHierarchy,
+    keyframes_query: &Query<&VoxKeyframes>,
+    model_query: &Query<&ModelKeyframe>,
+) -> Vec<Track> {
+    let mut tracks = Vec::new();
+    for entity in hierarchy.descendants(root) {
+        if let Ok(keyframes) = keyframes_query.get(entity) {
+            tracks.push(Track {
+                kind: TrackKind::Transform(entity),
+                last_frame: keyframes.last_frame(),
+            });
+        }
+        let models: Vec<(u32, Entity)> = hierarchy
+            .children(entity)
+            .filter_map(|child| model_query.get(child).ok().map(|key| (key.frame, child)))
+            .collect();
+        if !models.is_empty() {
+            tracks.push(Track {
+                last_frame: models.iter().map(|(key, _)| *key).max().unwrap_or(0),
+                kind: TrackKind::Models(models),
+            });
+        }
+    }
+    tracks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::prelude::Vec3;
+
+    fn frame(attributes: &[(&str, &str)]) -> Frame {
+        Frame {
+            attributes: attributes
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<Dict>(),
+        }
+    }
+
+    fn keyframes() -> VoxKeyframes {
+        VoxKeyframes::from_frames(&[
+            frame(&[("_f", "6"), ("_t", "10 0 0")]),
+            frame(&[("_f", "2"), ("_t", "0 0 0")]),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn frame_index_defaults_to_zero() {
+        assert_eq!(frame_index(&frame(&[("_f", "5")]).attributes), 5);
+        assert_eq!(frame_index(&frame(&[]).attributes), 0);
+        assert_eq!(frame_index(&frame(&[("_f", "x")]).attributes), 0);
+    }
+
+    #[test]
+    fn single_frame_is_not_animated() {
+        assert!(VoxKeyframes::from_frames(&[frame(&[("_t", "1 2 3")])]).is_none());
+    }
+
+    #[test]
+    fn keys_are_sorted_by_frame() {
+        let keys = keyframes();
+        assert_eq!(keys.keys[0].0, 2);
+        assert_eq!(keys.last_frame(), 6);
+    }
+
+    #[test]
+    fn sample_before_first_key() {
+        assert_eq!(keyframes().sample(0.0).translation, Vec3::ZERO);
+    }
+
+    #[test]
+    fn sample_after_last_key() {
+        assert_eq!(
+            keyframes().sample(9.0).translation,
+            Vec3::new(10.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn sample_between_keys() {
+        let translation = keyframes().sample(3.0).translation;
+        assert!((translation - Vec3::new(2.5, 0.0, 0.0)).length() < 1e-5);
+    }
+
+    #[test]
+    fn active_model_follows_frame() {
+        let (a, b, c) = (
+            Entity::from_raw(1),
+            Entity::from_raw(2),
+            Entity::from_raw(3),
+        );
+        let models = vec![(4, b), (1, a), (8, c)];
+        // 都在当前帧之后时显示第一个
+        assert_eq!(active_model(&models, 0.0), Some(a));
+        assert_eq!(active_model(&models, 1.0), Some(a));
+        assert_eq!(active_model(&models, 5.5), Some(b));
+        assert_eq!(active_model(&models, 8.0), Some(c));
+        assert_eq!(active_model(&[], 3.0), None);
+    }
+}
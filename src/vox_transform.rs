@@ -0,0 +1,130 @@
+// vox 场景中 nTRN 节点的变换
+// `_t` 是位移, `_r` 是一个字节编码的旋转 (可能带镜像)
+
+use bevy::prelude::{Mat3, Mat4, Transform, Vec3};
+use dot_vox::Frame;
+
+/// MagicaVoxel 是 z 轴向上, 和 bevy_vox_mesh 生成 mesh 时一样交换 y 和 z
+pub fn vox_to_bevy(x: f32, y: f32, z: f32) -> Vec3 {
+    Vec3::new(x, z, y)
+}
+
+// 交换 y 和 z 的矩阵, 它的逆矩阵就是它自己
+const SWAP_YZ: Mat3 = Mat3::from_cols(Vec3::X, Vec3::Z, Vec3::Y);
+
+/// 解码 `_r` 属性
+/// bit 0-1: 第一行非零元素的列, bit 2-3: 第二行非零元素的列
+/// bit 4/5/6: 第一/二/三行的符号 (1 为负)
+pub fn decode_rotation(byte: u8) -> Mat3 {
+    let first = (byte & 0b11) as usize;
+    let second = ((byte >> 2) & 0b11) as usize;
+    let third = 3usize.saturating_sub(first + second).min(2);
+    let mut rows = [[0.0f32; 3]; 3];
+    for (row, (column, sign_bit)) in [(first, 4), (second, 5), (third, 6)].iter().enumerate() {
+        let sign = if byte & (1 << sign_bit) != 0 {
+            -1.0
+        } else {
+            1.0
+        };
+        rows[row][(*column).min(2)] = sign;
+    }
+    // from_cols_array_2d 按列读取, 转置之后就是按行
+    Mat3::from_cols_array_2d(&rows).transpose()
+}
+
+/// 单帧的旋转, 已经转换到 bevy 的坐标系
+pub fn frame_rotation(frame: &Frame) -> Mat3 {
+    frame
+        .attributes
+        .get("_r")
+        .and_then(|r| r.parse::<u8>().ok())
+        .map_or(Mat3::IDENTITY, |byte| {
+            SWAP_YZ * decode_rotation(byte) * SWAP_YZ
+        })
+}
+
+/// 单帧的位移, 已经转换到 bevy 的坐标系
+pub fn frame_position(frame: &Frame) -> Vec3 {
+    frame.position().map_or(Vec3::ZERO, |pos| {
+        vox_to_bevy(pos.x as f32, pos.y as f32, pos.z as f32)
+    })
+}
+
+/// 单帧的完整变换, 镜像会变成负的缩放
+pub fn frame_transform(frame: &Frame) -> Transform {
+    let matrix = Mat4::from_mat3(frame_rotation(frame));
+    let mut transform = Transform::from_matrix(matrix);
+    transform.translation = frame_position(frame);
+    transform
+}
+
+/// transform 节点的静止姿态, 使用第一帧
+pub fn node_transform(frames: &[Frame]) -> Transform {
+    frames.first().map_or(Transform::IDENTITY, frame_transform)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dot_vox::Dict;
+
+    fn frame(attributes: &[(&str, &str)]) -> Frame {
+        Frame {
+            attributes: attributes
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<Dict>(),
+        }
+    }
+
+    #[test]
+    fn decode_identity() {
+        // 第一行在第 0 列, 第二行在第 1 列, 没有符号位
+        assert_eq!(decode_rotation(0b0000100), Mat3::IDENTITY);
+    }
+
+    #[test]
+    fn decode_rotation_around_z() {
+        // 第一行 (0, -1, 0), 第二行 (1, 0, 0)
+        let rotation = decode_rotation(0b0010001);
+        assert_eq!(rotation * Vec3::X, Vec3::Y);
+        assert_eq!(rotation * Vec3::Y, -Vec3::X);
+        assert_eq!(rotation * Vec3::Z, Vec3::Z);
+    }
+
+    #[test]
+    fn decode_mirrors() {
+        assert_eq!(
+            decode_rotation(0b0010100),
+            Mat3::from_diagonal(Vec3::new(-1.0, 1.0, 1.0))
+        );
+        assert_eq!(
+            decode_rotation(0b1000100),
+            Mat3::from_diagonal(Vec3::new(1.0, 1.0, -1.0))
+        );
+    }
+
+    #[test]
+    fn frame_rotation_swaps_y_and_z() {
+        // vox 中绕 z 轴旋转, 在 bevy 中是绕 y 轴, x 转到 vox 的 y 也就是 bevy 的 z
+        let rotation = frame_rotation(&frame(&[("_r", "17")]));
+        assert_eq!(rotation * Vec3::X, Vec3::Z);
+        assert_eq!(rotation * Vec3::Y, Vec3::Y);
+        // vox 的 z 镜像是 bevy 的 y 镜像
+        assert_eq!(
+            frame_rotation(&frame(&[("_r", "68")])),
+            Mat3::from_diagonal(Vec3::new(1.0, -1.0, 1.0))
+        );
+        assert_eq!(frame_rotation(&frame(&[])), Mat3::IDENTITY);
+    }
+
+    #[test]
+    fn mirrored_frame_transform() {
+        let transform = frame_transform(&frame(&[("_r", "20"), ("_t", "1 2 3")]));
+        assert_eq!(transform.translation, Vec3::new(1.0, 3.0, 2.0));
+        let point = transform
+            .compute_matrix()
+            .transform_point3(Vec3::new(1.0, 1.0, 1.0));
+        assert!(point.abs_diff_eq(Vec3::new(0.0, 4.0, 3.0), 1e-5));
+    }
+}
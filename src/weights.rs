@@ -0,0 +1,181 @@
+// 蒙皮权重
+// 按照顶点到关节的距离混合最多四个关节, 或者按调色板颜色绑定关节
+
+use bevy::{
+    prelude::{Color, Vec3},
+    utils::HashMap,
+};
+use serde::{Deserialize, Serialize};
+
+/// 一个顶点最多受几个关节影响, 和 `Mesh::ATTRIBUTE_JOINT_INDEX` 的宽度一致
+pub const MAX_INFLUENCES: usize = 4;
+
+/// 单个顶点的 joint index 和权重
+pub type VertexWeights = ([u16; 4], [f32; 4]);
+
+/// 根据距离自动计算权重
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DistanceWeights {
+    /// 超过这个距离的关节不再影响顶点
+    pub falloff_radius: f32,
+    /// 每个顶点最多混合的关节数量, 不超过 4
+    #[serde(default = "default_max_influences")]
+    pub max_influences: usize,
+}
+
+fn default_max_influences() -> usize {
+    MAX_INFLUENCES
+}
+
+impl DistanceWeights {
+    pub fn new(falloff_radius: f32) -> Self {
+        Self {
+            falloff_radius,
+            max_influences: MAX_INFLUENCES,
+        }
+    }
+
+    /// `joints` 是关节在 mesh 空间中的位置
+    /// 半径内没有关节时整个绑定到最近的关节
+    pub fn weights_for(&self, position: Vec3, joints: &[Vec3]) -> VertexWeights {
+        let max_influences = self.max_influences.clamp(1, MAX_INFLUENCES);
+        let mut influences: Vec<(usize, f32)> = joints
+            .iter()
+            .enumerate()
+            .filter_map(|(index, joint)| {
+                let t = 1.0 - position.distance(*joint) / self.falloff_radius;
+                if t > 0.0 {
+                    Some((index, t * t))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        influences.sort_by(|a, b| b.1.total_cmp(&a.1));
+        influences.truncate(max_influences);
+
+        if influences.is_empty() {
+            return single_joint(nearest_joint(position, joints));
+        }
+        let total: f32 = influences.iter().map(|(_, w)| w).sum();
+        let mut indices = [0u16; 4];
+        let mut weights = [0f32; 4];
+        for (slot, (index, weight)) in influences.iter().enumerate() {
+            indices[slot] = *index as u16;
+            weights[slot] = weight / total;
+        }
+        (indices, weights)
+    }
+}
+
+/// 整个绑定到一个关节
+pub fn single_joint(index: usize) -> VertexWeights {
+    ([index as u16, 0, 0, 0], [1.0, 0.0, 0.0, 0.0])
+}
+
+pub fn nearest_joint(position: Vec3, joints: &[Vec3]) -> usize {
+    joints
+        .iter()
+        .enumerate()
+        .min_by(|a, b| {
+            position
+                .distance_squared(*a.1)
+                .total_cmp(&position.distance_squared(*b.1))
+        })
+        .map(|(index, _)| index)
+        .unwrap_or(0)
+}
+
+/// 调色板序号 -> 关节名字
+/// 美术在 MagicaVoxel 里用不同的颜色涂不同的部位
+/// 序号和 `dot_vox::Voxel::i` 一致, 比 MagicaVoxel 界面上显示的序号小 1
+///
+/// ```ron
+/// weight_mode: Palette(joints: { 12: "left_arm", 13: "right_arm" }),
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PaletteWeights {
+    pub joints: HashMap<u8, String>,
+}
+
+/// 把 vox 调色板转换成和 bevy_vox_mesh 顶点色相同的格式
+pub fn vox_palette_colors(palette: &[dot_vox::Color]) -> Vec<[f32; 4]> {
+    palette
+        .iter()
+        .map(|color| Color::rgba_u8(color.r, color.g, color.b, color.a).as_linear_rgba_f32())
+        .collect()
+}
+
+/// 顶点色对应的调色板序号, 只接受完全相同的颜色
+/// 调色板中有相同的颜色时返回第一个, 见 [`duplicate_colors`]
+pub fn palette_index(color: [f32; 4], palette: &[[f32; 4]]) -> Option<u8> {
+    palette
+        .iter()
+        .position(|entry| *entry == color)
+        .map(|index| index as u8)
+}
+
+/// 调色板中重复的颜色, `(第一次出现的序号, 重复的序号)`
+/// 重复序号上的顶点无法和第一个区分
+pub fn duplicate_colors(palette: &[[f32; 4]]) -> Vec<(u8, u8)> {
+    palette
+        .iter()
+        .enumerate()
+        .filter_map(|(index, color)| {
+            let first = palette_index(*color, palette)?;
+            (first as usize != index).then_some((first, index as u8))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn total(weights: &[f32; 4]) -> f32 {
+        weights.iter().sum()
+    }
+
+    #[test]
+    fn distance_weights_are_normalized() {
+        let joints = [
+            Vec3::ZERO,
+            Vec3::new(4.0, 0.0, 0.0),
+            Vec3::new(0.0, 4.0, 0.0),
+        ];
+        let (indices, weights) =
+            DistanceWeights::new(10.0).weights_for(Vec3::new(1.0, 1.0, 0.0), &joints);
+        assert!((total(&weights) - 1.0).abs() < 1e-5);
+        // 最近的关节权重最大
+        assert_eq!(indices[0], 0);
+        assert!(weights[0] > weights[1] && weights[1] >= weights[2]);
+    }
+
+    #[test]
+    fn max_influences_limits_joints() {
+        let joints = [Vec3::ZERO, Vec3::X, Vec3::Y, Vec3::Z, Vec3::ONE];
+        let distance = DistanceWeights {
+            falloff_radius: 10.0,
+            max_influences: 2,
+        };
+        let (_, weights) = distance.weights_for(Vec3::splat(0.1), &joints);
+        assert_eq!(weights[2], 0.0);
+        assert_eq!(weights[3], 0.0);
+        assert!((total(&weights) - 1.0).abs() < 1e-5);
+
+        // 超过 4 时按 4 处理
+        let distance = DistanceWeights {
+            falloff_radius: 10.0,
+            max_influences: 8,
+        };
+        let (_, weights) = distance.weights_for(Vec3::splat(0.1), &joints);
+        assert!((total(&weights) - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn outside_radius_uses_nearest_joint() {
+        let joints = [Vec3::ZERO, Vec3::new(100.0, 0.0, 0.0)];
+        let weights = DistanceWeights::new(1.0).weights_for(Vec3::new(90.0, 0.0, 0.0), &joints);
+        assert_eq!(weights, single_joint(1));
+    }
+}